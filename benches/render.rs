@@ -0,0 +1,84 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mold::Mold;
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+fn plain_config(lines: usize) -> String {
+    (0..lines)
+        .map(|i| format!("option_{} = value {}\n", i, i))
+        .collect()
+}
+
+fn json_config(entries: usize) -> String {
+    let body = (0..entries)
+        .map(|i| format!("\"key_{}\": {{ \"value\": {{ \"id\": {} }} }}", i, i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{{ {}, \"theme\": \"{{% theme %}}\" }}", body)
+}
+
+fn render_benchmark(c: &mut Criterion) {
+    let mold = Mold::default();
+    let plain = plain_config(10_000);
+    // a single tag at the end forces the whole input through the parser
+    let tagged = format!("{}{{% missing %}}", plain);
+
+    let mut group = c.benchmark_group("tag-free input");
+    group.bench_function("fast path", |b| {
+        b.iter(|| mold.render(black_box(&plain), None, false).unwrap())
+    });
+    group.bench_function("parser path", |b| {
+        b.iter(|| mold.render(black_box(&tagged), None, false).unwrap())
+    });
+    group.finish();
+
+    let json = json_config(5_000);
+    c.bench_function("brace heavy json", |b| {
+        b.iter(|| mold.render(black_box(&json), None, false).unwrap())
+    });
+}
+
+/// Writes `files` templates of `lines` lines each to a temporary directory.
+fn template_tree(files: usize, lines: usize) -> Vec<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("mold-bench-tree-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let template = format!("{}{{% theme %}}\n", plain_config(lines));
+    (0..files)
+        .map(|i| {
+            let path = dir.join(format!("template_{}.conf", i));
+            std::fs::write(&path, &template).unwrap();
+            path
+        })
+        .collect()
+}
+
+fn tree_benchmark(c: &mut Criterion) {
+    let mold = Mold::default();
+    let templates = template_tree(300, 500);
+
+    let mut group = c.benchmark_group("template tree");
+    group.bench_function("serial", |b| {
+        b.iter(|| {
+            templates
+                .iter()
+                .map(|template| mold.render_file(black_box(template), None, false).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| {
+            templates
+                .par_iter()
+                .map(|template| mold.render_file(black_box(template), None, false).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    group.finish();
+
+    if let Some(dir) = templates.first().and_then(|template| template.parent()) {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+criterion_group!(benches, render_benchmark, tree_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,165 @@
+/// Sequences that start a tag, text of the adopted file containing them is kept in raw blocks.
+const TAG_STARTS: &[&str] = &["{%", "{@", "{!"];
+
+/// Replaces every occurrence of a variable value in `content` with a tag of the variable. Longer
+/// values are matched first so that a value that is a part of another one doesn't split it, and
+/// the inserted tags are never matched again. Text that would be parsed as a tag is wrapped in
+/// raw blocks so that the template renders back to `content`. Returns the template and the number
+/// of substitutions of every variable in the order of `variables`.
+pub fn substitute(content: &str, variables: &[(String, String)]) -> (String, Vec<usize>) {
+    let mut order: Vec<usize> = (0..variables.len())
+        .filter(|i| !variables[*i].1.is_empty())
+        .collect();
+    order.sort_by(|a, b| {
+        let (a_name, a_value) = &variables[*a];
+        let (b_name, b_value) = &variables[*b];
+        b_value.len().cmp(&a_value.len()).then(a_name.cmp(b_name))
+    });
+
+    let mut counts = vec![0; variables.len()];
+    let mut template = String::with_capacity(content.len());
+    let mut rest = content;
+    // start of the text between the substituted values
+    let mut text = content;
+    while let Some(c) = rest.chars().next() {
+        match order
+            .iter()
+            .find(|i| rest.starts_with(variables[**i].1.as_str()))
+        {
+            Some(&i) => {
+                let (name, value) = &variables[i];
+                push_text(&mut template, &text[..text.len() - rest.len()]);
+                template.push_str("{% ");
+                template.push_str(name);
+                template.push_str(" %}");
+                counts[i] += 1;
+                rest = &rest[value.len()..];
+                text = rest;
+            }
+            None => rest = &rest[c.len_utf8()..],
+        }
+    }
+    push_text(&mut template, text);
+    (template, counts)
+}
+
+/// Appends text of the adopted file to `template`, in a raw block if it contains a tag start. Text
+/// that contains `endraw` can't be a part of a single raw block, so only its tag starts are.
+fn push_text(template: &mut String, text: &str) {
+    if !TAG_STARTS.iter().any(|start| text.contains(start)) {
+        template.push_str(text);
+    } else if !text.contains("endraw") {
+        template.push_str("{% raw %}");
+        template.push_str(text);
+        template.push_str("{% endraw %}");
+    } else {
+        let mut rest = text;
+        while let Some(pos) = rest.find('{') {
+            template.push_str(&rest[..pos]);
+            rest = &rest[pos..];
+            match TAG_STARTS.iter().find(|start| rest.starts_with(**start)) {
+                Some(start) => {
+                    template.push_str("{% raw %}");
+                    template.push_str(start);
+                    template.push_str("{% endraw %}");
+                    rest = &rest[start.len()..];
+                }
+                None => {
+                    template.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        template.push_str(rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mold::Mold;
+
+    fn variables(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Renders `template` with `variables` in the global namespace.
+    fn render(template: &str, variables: &[(String, String)]) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let context = dir.path().join("context.yaml");
+        let mut yaml = String::from("namespaces:\n  - name: GLOBAL\n    variables:\n");
+        for (name, value) in variables {
+            yaml.push_str(&format!("      {}: {:?}\n", name, value));
+        }
+        std::fs::write(&context, yaml).unwrap();
+        Mold::new(&context)
+            .unwrap()
+            .render(template, None, false)
+            .unwrap()
+    }
+
+    #[test]
+    fn longer_values_are_substituted_first() {
+        let variables = variables(&[("bg", "#282828"), ("bg_hard", "#282828ff")]);
+        let content = "background = #282828ff\nborder = #282828\n";
+        let (template, counts) = substitute(content, &variables);
+        assert_eq!(template, "background = {% bg_hard %}\nborder = {% bg %}\n");
+        assert_eq!(counts, [1, 1]);
+        assert_eq!(render(&template, &variables), content);
+    }
+
+    #[test]
+    fn overlapping_values_take_the_first_match() {
+        // neither value contains the other, the one starting first wins
+        let variables = variables(&[("ab", "ab"), ("bc", "bc"), ("empty", "")]);
+        let (template, counts) = substitute("abc bca", &variables);
+        assert_eq!(template, "{% ab %}c {% bc %}a");
+        assert_eq!(counts, [1, 1, 0]);
+    }
+
+    #[test]
+    fn inserted_tags_are_not_matched_again() {
+        let variables = variables(&[("one", "1"), ("name", "ab")]);
+        let (template, counts) = substitute("ab1", &variables);
+        assert_eq!(template, "{% name %}{% one %}");
+        assert_eq!(counts, [1, 1]);
+    }
+
+    #[test]
+    fn multibyte_text_is_kept() {
+        let variables = variables(&[("accent", "é"), ("font", "Noto Sans 日本")]);
+        let content = "é → font: Noto Sans 日本 ✓";
+        let (template, counts) = substitute(content, &variables);
+        assert_eq!(template, "{% accent %} → font: {% font %} ✓");
+        assert_eq!(counts, [1, 1]);
+        assert_eq!(render(&template, &variables), content);
+    }
+
+    #[test]
+    fn text_with_tag_starts_is_raw() {
+        let variables = variables(&[("font", "mono")]);
+        let content = "font = mono\n# {% jinja %} and {@ path @}\nprompt = '{!'\nfont = mono\n";
+        let (template, counts) = substitute(content, &variables);
+        assert_eq!(
+            template,
+            "font = {% font %}{% raw %}\n# {% jinja %} and {@ path @}\nprompt = '{!'\nfont = {% endraw %}{% font %}\n"
+        );
+        assert_eq!(counts, [2]);
+        assert_eq!(render(&template, &variables), content);
+    }
+
+    #[test]
+    fn text_with_endraw_keeps_only_tag_starts_raw() {
+        let variables = variables(&[("font", "mono")]);
+        let content = "{% raw %}{% endraw %} {mono}";
+        let (template, _) = substitute(content, &variables);
+        assert_eq!(
+            template,
+            "{% raw %}{%{% endraw %} raw %}{% raw %}{%{% endraw %} endraw %} {{% font %}}"
+        );
+        assert_eq!(render(&template, &variables), content);
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Delimiter of the here-documents in scripts, a number is appended if the content contains it.
+const HEREDOC_DELIMITER: &str = "MOLD_EOF";
+
+/// A file that mold would write.
+pub struct Change<'a> {
+    /// Absolute path of the file.
+    pub path: PathBuf,
+    /// Current content of the file or `None` if it doesn't exist.
+    pub current: Option<String>,
+    pub content: &'a str,
+    /// Symlink that should point to the file.
+    pub link: Option<PathBuf>,
+}
+
+/// Returns `path` relative to the root directory, so that the patch applies with `patch -p0`
+/// run from `/`.
+fn relative_to_root(path: &Path) -> String {
+    path.to_string_lossy().trim_start_matches('/').to_string()
+}
+
+/// Returns a unified diff of all `changes` in the order they are given, new files are diffed
+/// against `/dev/null`.
+pub fn patch(changes: &[Change<'_>]) -> String {
+    let mut patch = String::new();
+    for change in changes {
+        let new = relative_to_root(&change.path);
+        let old = match change.current {
+            Some(_) => new.as_str(),
+            None => "/dev/null",
+        };
+        let diff =
+            similar::TextDiff::from_lines(change.current.as_deref().unwrap_or(""), change.content);
+        patch.push_str(
+            &diff
+                .unified_diff()
+                .context_radius(3)
+                .header(old, &new)
+                .to_string(),
+        );
+    }
+    patch
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Returns a delimiter of a here-document that doesn't occur as a line of `content`.
+fn heredoc_delimiter(content: &str) -> String {
+    let mut delimiter = HEREDOC_DELIMITER.to_string();
+    let mut n = 1;
+    while content.lines().any(|line| line == delimiter) {
+        delimiter = format!("{}_{}", HEREDOC_DELIMITER, n);
+        n += 1;
+    }
+    delimiter
+}
+
+/// Returns a POSIX shell script that writes the content of all `changes` to their paths.
+pub fn script(changes: &[Change<'_>]) -> String {
+    let mut script =
+        String::from("#!/bin/sh\n# Generated by mold, writes the rendered files.\nset -e\n");
+    for change in changes {
+        let path = quote(&change.path.to_string_lossy());
+        script.push('\n');
+        if let Some(parent) = change.path.parent() {
+            let _ = writeln!(script, "mkdir -p {}", quote(&parent.to_string_lossy()));
+        }
+        if change.content.is_empty() {
+            let _ = writeln!(script, ": > {}", path);
+        } else if change.content.ends_with('\n') {
+            let delimiter = heredoc_delimiter(change.content);
+            let _ = writeln!(script, "cat > {} <<'{}'", path, delimiter);
+            script.push_str(change.content);
+            let _ = writeln!(script, "{}", delimiter);
+        } else {
+            // a here-document always ends with a newline
+            let _ = writeln!(script, "printf '%s' {} > {}", quote(change.content), path);
+        }
+        if let Some(link) = &change.link {
+            let _ = writeln!(
+                script,
+                "ln -sfn {} {}",
+                path,
+                quote(&link.to_string_lossy())
+            );
+        }
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change<'a>(path: &str, current: Option<&str>, content: &'a str) -> Change<'a> {
+        Change {
+            path: PathBuf::from(path),
+            current: current.map(str::to_string),
+            content,
+            link: None,
+        }
+    }
+
+    #[test]
+    fn patch_of_new_file_is_against_dev_null() {
+        assert_eq!(
+            patch(&[change("/etc/new.conf", None, "a\nb\n")]),
+            "--- /dev/null\n+++ etc/new.conf\n@@ -0,0 +1,2 @@\n+a\n+b\n"
+        );
+    }
+
+    #[test]
+    fn patch_of_changed_files() {
+        assert_eq!(
+            patch(&[
+                change("/home/me/a.conf", Some("x = 1\ny = 2\n"), "x = 1\ny = 3\n"),
+                change("/home/me/b.conf", Some("same\n"), "same\n"),
+                change("/home/me/c.conf", Some("old"), "new"),
+            ]),
+            "--- home/me/a.conf\n+++ home/me/a.conf\n@@ -1,2 +1,2 @@\n x = 1\n-y = 2\n+y = 3\n\
+             --- home/me/c.conf\n+++ home/me/c.conf\n@@ -1 +1 @@\n-old\n\\ No newline at end of file\n\
+             +new\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn delimiter_does_not_occur_in_content() {
+        assert_eq!(heredoc_delimiter("a\nMOLD_EOF_1\n"), "MOLD_EOF");
+        assert_eq!(heredoc_delimiter("a\n  MOLD_EOF\n"), "MOLD_EOF");
+        assert_eq!(heredoc_delimiter("MOLD_EOF\n"), "MOLD_EOF_1");
+        assert_eq!(
+            heredoc_delimiter("MOLD_EOF\nMOLD_EOF_1\nMOLD_EOF_2\n"),
+            "MOLD_EOF_3"
+        );
+    }
+
+    #[test]
+    fn script_writes_every_kind_of_content() {
+        let mut linked = change("/home/me/it's.conf", None, "MOLD_EOF\n$HOME\n");
+        linked.link = Some(PathBuf::from("/home/me/link.conf"));
+        assert_eq!(
+            script(&[
+                linked,
+                change("/tmp/partial", None, "no newline 'quoted'"),
+                change("/tmp/empty", Some("old\n"), ""),
+            ]),
+            "#!/bin/sh\n# Generated by mold, writes the rendered files.\nset -e\n\
+             \n\
+             mkdir -p '/home/me'\n\
+             cat > '/home/me/it'\\''s.conf' <<'MOLD_EOF_1'\n\
+             MOLD_EOF\n$HOME\n\
+             MOLD_EOF_1\n\
+             ln -sfn '/home/me/it'\\''s.conf' '/home/me/link.conf'\n\
+             \n\
+             mkdir -p '/tmp'\n\
+             printf '%s' 'no newline '\\''quoted'\\''' > '/tmp/partial'\n\
+             \n\
+             mkdir -p '/tmp'\n\
+             : > '/tmp/empty'\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn script_recreates_the_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("full", "MOLD_EOF\n$HOME `date`\n"),
+            ("partial", "it's\n\\n"),
+            ("empty", ""),
+        ];
+        let changes: Vec<_> = files
+            .iter()
+            .map(|(name, content)| Change {
+                path: dir.path().join("out").join(name),
+                current: None,
+                content,
+                link: None,
+            })
+            .collect();
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(script(&changes))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        for (name, content) in files {
+            assert_eq!(
+                std::fs::read_to_string(dir.path().join("out").join(name)).unwrap(),
+                content
+            );
+        }
+    }
+}
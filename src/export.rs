@@ -0,0 +1,80 @@
+/// Shell syntax of exported variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum ExportFormat {
+    /// `export NAME='value'` understood by POSIX shells.
+    Sh,
+    /// `set -gx NAME 'value'` for fish.
+    Fish,
+}
+
+/// Turns a variable name into a valid shell identifier by uppercasing it and replacing all other
+/// characters than ASCII letters, digits and `_` with `_`.
+pub fn shell_identifier(prefix: &str, key: &str) -> String {
+    let mut identifier: String = prefix
+        .chars()
+        .chain(key.chars())
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    identifier
+}
+
+/// Returns a line that sets the environment variable `name` to `value` when evaluated by the
+/// shell. The value is single quoted so that it's never expanded.
+pub fn export_line(format: ExportFormat, name: &str, value: &str) -> String {
+    match format {
+        ExportFormat::Sh => format!("export {}='{}'", name, value.replace('\'', r"'\''")),
+        ExportFormat::Fish => format!(
+            "set -gx {} '{}'",
+            name,
+            value.replace('\\', r"\\").replace('\'', r"\'")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_become_shell_identifiers() {
+        assert_eq!(shell_identifier("", "font.size"), "FONT_SIZE");
+        assert_eq!(shell_identifier("MOLD_", "bg-color"), "MOLD_BG_COLOR");
+        assert_eq!(shell_identifier("", "0xff"), "_0XFF");
+        assert_eq!(shell_identifier("MOLD_", "0xff"), "MOLD_0XFF");
+        // every character is replaced, not every byte
+        assert_eq!(shell_identifier("", "café"), "CAF_");
+        assert_eq!(shell_identifier("", "日本"), "__");
+    }
+
+    #[test]
+    fn sh_values_are_single_quoted() {
+        assert_eq!(export_line(ExportFormat::Sh, "A", "b c"), "export A='b c'");
+        assert_eq!(
+            export_line(ExportFormat::Sh, "A", "it's $HOME"),
+            r"export A='it'\''s $HOME'"
+        );
+        assert_eq!(export_line(ExportFormat::Sh, "A", r"\n"), r"export A='\n'");
+        assert_eq!(export_line(ExportFormat::Sh, "A", ""), "export A=''");
+    }
+
+    #[test]
+    fn fish_values_escape_backslashes_and_quotes() {
+        assert_eq!(
+            export_line(ExportFormat::Fish, "A", "it's $HOME"),
+            r"set -gx A 'it\'s $HOME'"
+        );
+        assert_eq!(
+            export_line(ExportFormat::Fish, "A", r"C:\path\'"),
+            r"set -gx A 'C:\\path\\\''"
+        );
+    }
+}
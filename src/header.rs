@@ -0,0 +1,66 @@
+use std::path::Path;
+
+/// Comment leader used when the extension of the output has no entry in `COMMENT_LEADERS`.
+const DEFAULT_COMMENT_LEADER: &str = "#";
+
+/// Comment leaders of common config formats keyed by the file extension.
+const COMMENT_LEADERS: &[(&str, &str)] = &[
+    ("c", "//"),
+    ("cpp", "//"),
+    ("h", "//"),
+    ("js", "//"),
+    ("jsonc", "//"),
+    ("rs", "//"),
+    ("scss", "//"),
+    ("ts", "//"),
+    ("vim", "\""),
+    ("ini", ";"),
+    ("el", ";"),
+    ("lua", "--"),
+    ("sql", "--"),
+    ("xdefaults", "!"),
+    ("xresources", "!"),
+];
+
+/// Returns the comment leader for `output` based on its extension.
+pub fn comment_leader(output: &Path) -> &'static str {
+    let extension = match output.extension().and_then(|ext| ext.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return DEFAULT_COMMENT_LEADER,
+    };
+    COMMENT_LEADERS
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, leader)| *leader)
+        .unwrap_or(DEFAULT_COMMENT_LEADER)
+}
+
+/// Prepends a comment to `content` marking it as generated from `template`. The header is
+/// deterministic so that outputs rendered with it can still be compared with fresh renders. A
+/// shebang line is kept as the first line.
+pub fn with_header(
+    content: &str,
+    leader: &str,
+    template: &Path,
+    namespace: Option<&str>,
+) -> String {
+    let template = template.file_name().unwrap_or(template.as_os_str());
+    let mut header = format!(
+        "{} Generated by mold from {}",
+        leader,
+        template.to_string_lossy()
+    );
+    if let Some(namespace) = namespace {
+        header.push_str(&format!(" (namespace: {})", namespace));
+    }
+    header.push_str(" — do not edit\n");
+
+    if content.starts_with("#!") {
+        let (shebang, rest) =
+            content.split_at(content.find('\n').map_or(content.len(), |pos| pos + 1));
+        let separator = if shebang.ends_with('\n') { "" } else { "\n" };
+        format!("{}{}{}{}", shebang, separator, header, rest)
+    } else {
+        header + content
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Context as ErrorContext, Result};
+use indexmap::IndexMap;
+use serde_yaml::{Mapping, Value};
+use std::path::Path;
+
+/// What happens with a namespace of the same name that already exists in the context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Existing {
+    /// The namespace is replaced as a whole.
+    Replace,
+    /// Imported variables are added to the namespace overriding variables with the same name.
+    Merge,
+    /// The import fails.
+    Error,
+}
+
+/// Returns the YAML representation of a namespace as it appears in the `namespaces` list.
+pub fn namespace_value(name: &str, variables: &IndexMap<String, String>) -> Value {
+    let mut namespace = Mapping::new();
+    namespace.insert("name".into(), name.into());
+    namespace.insert(
+        "variables".into(),
+        Value::Mapping(variables_mapping(variables)),
+    );
+    Value::Mapping(namespace)
+}
+
+fn variables_mapping(variables: &IndexMap<String, String>) -> Mapping {
+    variables
+        .iter()
+        .map(|(key, value)| (key.as_str().into(), value.as_str().into()))
+        .collect()
+}
+
+/// Adds a namespace with `variables` to the context file. The context is written back as a whole
+/// so comments and formatting are not preserved.
+pub fn write_namespace(
+    context_file: &Path,
+    name: &str,
+    variables: &IndexMap<String, String>,
+    existing: Existing,
+) -> Result<()> {
+    let mut context = load(context_file)?;
+    let namespaces = context
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("context is not a mapping"))?
+        .entry("namespaces".into())
+        .or_insert_with(|| Value::Sequence(vec![]))
+        .as_sequence_mut()
+        .ok_or_else(|| anyhow!("`namespaces` is not a list"))?;
+    let namespace = namespaces
+        .iter_mut()
+        .find(|namespace| namespace.get("name").and_then(Value::as_str) == Some(name));
+    match (namespace, existing) {
+        (None, _) => namespaces.push(namespace_value(name, variables)),
+        (Some(namespace), Existing::Replace) => *namespace = namespace_value(name, variables),
+        (Some(namespace), Existing::Merge) => {
+            let current = namespace
+                .as_mapping_mut()
+                .ok_or_else(|| anyhow!("namespace `{}` is not a mapping", name))?
+                .entry("variables".into())
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            match current.as_mapping_mut() {
+                Some(current) => current.extend(variables_mapping(variables)),
+                None => *current = Value::Mapping(variables_mapping(variables)),
+            }
+        }
+        (Some(_), Existing::Error) => {
+            return Err(anyhow!(
+                "namespace `{}` already exists, use `--merge` or `--replace`",
+                name
+            ))
+        }
+    }
+    save(context_file, &context)
+}
+
+/// Adds an entry rendering `template` to `output` to the `renders` of the context file. The
+/// context is written back as a whole so comments and formatting are not preserved.
+pub fn add_render(context_file: &Path, template: &Path, output: &Path) -> Result<()> {
+    let mut context = load(context_file)?;
+    let renders = context
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("context is not a mapping"))?
+        .entry("renders".into())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if renders.is_null() {
+        *renders = Value::Mapping(Mapping::new());
+    }
+    let renders = renders
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("`renders` is not a mapping"))?;
+    let template: Value = template.to_string_lossy().as_ref().into();
+    let output: Value = output.to_string_lossy().as_ref().into();
+    match renders.get(&template) {
+        Some(existing) if *existing == output => return Ok(()),
+        Some(_) => {
+            return Err(anyhow!(
+                "template `{}` is already rendered to a different output",
+                template.as_str().unwrap_or_default()
+            ))
+        }
+        None => {}
+    }
+    renders.insert(template, output);
+    save(context_file, &context)
+}
+
+fn load(context_file: &Path) -> Result<Value> {
+    let data = std::fs::read(context_file)
+        .with_context(|| format!("failed to read context file `{}`", context_file.display()))?;
+    serde_yaml::from_slice(&data)
+        .with_context(|| format!("failed to parse context file `{}`", context_file.display()))
+}
+
+fn save(context_file: &Path, context: &Value) -> Result<()> {
+    let data = serde_yaml::to_string(context).context("context serialization error")?;
+    std::fs::write(context_file, data).context("failed to write context file")
+}
@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Context as ErrorContext, Result};
+use log::warn;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Starter context written by `mold init`.
+pub const EXAMPLE_CONTEXT: &str = r##"# Context file of mold, see https://github.com/vv9k/mold for all options.
+
+# Version of the context file format.
+version: 1
+
+# Templates mapped to the location where they are rendered by `mold render-context`. Relative
+# template paths are relative to the current directory.
+renders:
+  example.conf.mold: ~/.config/example/example.conf
+
+namespaces:
+  # Variables of the GLOBAL namespace are available when rendering with any namespace. They are
+  # used in templates like this: {% font %}
+  - name: GLOBAL
+    variables:
+      font: JetBrains Mono
+      font_size: "11"
+
+  # Select a namespace with `--namespace dark`, its variables take precedence over the GLOBAL ones.
+  - name: dark
+    variables:
+      background: "#1d2021"
+      foreground: "#ebdbb2"
+"##;
+
+/// Returns all regular files in `dir` and its subdirectories sorted by path. Symbolic links to
+/// files are included, links to directories are not followed so a link can't make the walk loop.
+/// Links to directories, dangling links and special files like sockets are skipped with a warning.
+pub fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)
+            .with_context(|| format!("failed to read directory `{}`", dir.display()))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file()
+                || (file_type.is_symlink() && path.metadata().is_ok_and(|meta| meta.is_file()))
+            {
+                files.push(path);
+            } else {
+                warn!("skipping `{}` which is not a file", path.display());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns a context listing every template in `dir` and all variables they reference with
+/// empty values in the GLOBAL namespace. Files that are not valid templates are skipped.
+pub fn context_from_templates(dir: &Path) -> Result<String> {
+    let mut templates = vec![];
+    let mut variables: Vec<String> = vec![];
+    for file in files(dir)? {
+        let referenced = match std::fs::read_to_string(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| mold::referenced_variables(&content))
+        {
+            Ok(referenced) => referenced,
+            Err(e) => {
+                warn!("skipping `{}` - {:?}", file.display(), e);
+                continue;
+            }
+        };
+        for name in referenced {
+            if !variables.contains(&name) {
+                variables.push(name);
+            }
+        }
+        templates.push(file);
+    }
+
+    let mut context = String::from(
+        "# Context file of mold generated from the templates in the directory, fill in the values\n\
+         # of the variables.\n\n",
+    );
+    writeln!(context, "version: {}\n", mold::CONTEXT_VERSION)?;
+    context.push_str(
+        "# Templates found in the directory, add them to `renders` with their outputs:\n",
+    );
+    for template in &templates {
+        writeln!(context, "#   {}: ~/.config/", template.display())?;
+    }
+    context.push_str("renders: {}\n\nnamespaces:\n  - name: GLOBAL\n");
+    if variables.is_empty() {
+        context.push_str("    variables: {}\n");
+    } else {
+        context.push_str("    variables:\n");
+    }
+    for name in &variables {
+        // variable names can't contain quotes or backslashes so they don't have to be escaped
+        writeln!(context, "      \"{}\": \"\"", name)?;
+    }
+    Ok(context)
+}
+
+/// Checks that `context` loads with mold, so that `mold init` never writes an invalid context.
+pub fn validate(context: &str) -> Result<()> {
+    let dir = tempfile::tempdir().context("failed to create a temporary directory")?;
+    let path = dir.path().join("context.yaml");
+    std::fs::write(&path, context)
+        .with_context(|| format!("failed to write `{}`", path.display()))?;
+    mold::Mold::new(&path)
+        .map(|_| ())
+        .map_err(|e| anyhow!("the generated context is invalid - {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_context_is_valid() {
+        validate(EXAMPLE_CONTEXT).unwrap();
+    }
+
+    #[test]
+    fn context_from_templates_is_valid() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("a.mold"), "{% font %} {% mold.hostname %}").unwrap();
+        std::fs::write(
+            dir.path().join("nested/b.mold"),
+            "{% if accent %}{% accent | upper %}{% endif %} {% font %} {% my-var_2 %}",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("invalid.mold"), "{% %}").unwrap();
+        let context = context_from_templates(dir.path()).unwrap();
+        validate(&context).unwrap();
+        // builtins are left out, every variable is listed once in the order of the templates
+        assert!(
+            context.ends_with(
+                "  - name: GLOBAL\n    variables:\n      \"font\": \"\"\n      \"accent\": \"\"\n      \"my-var_2\": \"\"\n"
+            ),
+            "{}",
+            context
+        );
+        assert!(!context.contains("invalid.mold"), "{}", context);
+    }
+
+    #[test]
+    fn invalid_context_is_rejected() {
+        assert!(validate("namespaces: [").is_err());
+    }
+}
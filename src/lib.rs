@@ -1,11 +1,22 @@
 mod parser;
+mod source;
+pub mod xresources;
+
+pub use source::{FileSystemSource, MemorySource, TemplateSource};
 
 use parser::Token;
 
-use anyhow::{Context as ErrorContext, Result};
+use anyhow::{anyhow, Context as ErrorContext, Result};
+use indexmap::IndexMap;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
 pub type VariableKey = String;
 pub type VariableValue = String;
@@ -13,60 +24,966 @@ pub type VariableValue = String;
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Namespace {
     name: String,
+    #[serde(default)]
     variables: HashMap<VariableKey, VariableValue>,
+    /// Dotenv file whose variables are added to the namespace when the context is loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from_env_file: Option<PathBuf>,
+    /// Whether a missing `from_env_file` is ignored.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    optional: bool,
+    /// Keys of variables whose values are redacted in diffs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    secrets: Vec<VariableKey>,
 }
 
 pub const GLOBAL_NS: &str = "GLOBAL";
 
+/// Version of the context file format understood by this version of mold. Context files without
+/// a `version` field are assumed to be of this version.
+pub const CONTEXT_VERSION: u32 = 1;
+
+fn default_context_version() -> u32 {
+    CONTEXT_VERSION
+}
+
+/// Filter that treats the value of a variable as a path to a file whose rendered content
+/// is used in place of the variable.
+const FILE_FILTER: &str = "file";
+/// Filter that indents every line but the first one by the width given as the argument, like
+/// `indent:4`. With `indent:auto` the lines are indented to the column of the tag.
+const INDENT_FILTER: &str = "indent";
+const INDENT_AUTO: &str = "auto";
+/// Filter that replaces all occurrences of the first quoted argument with the second one, like
+/// `replace("/", "_")`.
+const REPLACE_FILTER: &str = "replace";
+
+/// Byte order mark that editors on Windows put at the start of UTF-8 files.
+const BOM: char = '\u{feff}';
+
+/// Removes a leading byte order mark of `content`, otherwise it would end up in the middle of
+/// rendered output or hide a front-matter block.
+fn strip_bom(mut content: String) -> String {
+    if content.starts_with(BOM) {
+        content.drain(..BOM.len_utf8());
+    }
+    content
+}
+
+/// Prefixes every line of `content` after the first one with `width` spaces, blank lines are
+/// left empty.
+fn indent(content: &str, width: usize) -> String {
+    let prefix = " ".repeat(width);
+    let mut indented = String::with_capacity(content.len());
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        if i > 0 && !line.trim_end_matches(['\r', '\n']).is_empty() {
+            indented.push_str(&prefix);
+        }
+        indented.push_str(line);
+    }
+    indented
+}
+
+/// Whether the file or directory at `path` of an `if_exists` block exists, the path is expanded
+/// with [`expand_path`] like every other path.
+fn path_exists(path: &str) -> bool {
+    expand_path(Path::new(path)).exists()
+}
+
+/// Writer that keeps track of the column at which the next write starts.
+struct ColumnWriter<'w, W> {
+    inner: &'w mut W,
+    column: usize,
+}
+
+impl<W: Write> Write for ColumnWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = &buf[..self.inner.write(buf)?];
+        let line = match written.iter().rposition(|b| *b == b'\n') {
+            Some(pos) => {
+                self.column = 0;
+                &written[pos + 1..]
+            }
+            None => written,
+        };
+        // count characters rather than bytes, continuation bytes of UTF-8 are skipped
+        self.column += line.iter().filter(|b| (**b & 0xC0) != 0x80).count();
+        Ok(written.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 impl Namespace {
     pub fn global() -> Self {
         Self {
             name: GLOBAL_NS.to_string(),
-            variables: HashMap::new(),
+            ..Default::default()
+        }
+    }
+
+    /// Reads the `KEY=VALUE` pairs of a dotenv file into a namespace named after the file stem.
+    pub fn from_env_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map(strip_bom)
+            .with_context(|| format!("failed to read env file `{}`", path.display()))?;
+        Ok(Self {
+            name: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            variables: parse_env_file(&content)
+                .with_context(|| format!("failed to parse env file `{}`", path.display()))?,
+            ..Default::default()
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn variables(&self) -> &HashMap<VariableKey, VariableValue> {
+        &self.variables
+    }
+
+    /// Returns the variables of the namespace sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&VariableKey, &VariableValue)> {
+        let mut variables: Vec<_> = self.variables.iter().collect();
+        variables.sort_unstable_by_key(|(key, _)| *key);
+        variables.into_iter()
+    }
+
+    /// Returns the raw value of the variable `key` defined in this namespace, nested variables
+    /// are not rendered and the global namespace is not consulted.
+    pub fn get(&self, key: &str) -> Option<&VariableValue> {
+        self.variables.get(key)
+    }
+
+    /// Keys of the variables marked as secret in this namespace.
+    pub fn secrets(&self) -> &[VariableKey] {
+        &self.secrets
+    }
+
+    /// Adds the variables of `from_env_file` to the namespace, variables listed explicitly take
+    /// precedence.
+    fn load_env_file(&mut self) -> Result<()> {
+        let path = match &self.from_env_file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if self.optional && !path.exists() {
+            debug!("skipping missing optional env file `{}`", path.display());
+            return Ok(());
+        }
+        for (key, value) in Namespace::from_env_file(path)?.variables {
+            self.variables.entry(key).or_insert(value);
+        }
+        Ok(())
+    }
+
+    /// Expands references to environment variables in all variable values.
+    fn expand_env(&mut self, missing: MissingEnv) -> Result<()> {
+        for (key, value) in self.variables.iter_mut() {
+            *value = expand_env(value, missing)
+                .with_context(|| format!("failed to expand variable `{}`", key))?;
+        }
+        Ok(())
+    }
+}
+
+/// A render entry can be either just the output path or a map with additional options.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum SerializedRenderEntry {
+    Output(PathBuf),
+    Entry {
+        output: PathBuf,
+        #[serde(default, alias = "post")]
+        on_change: Option<String>,
+        #[serde(default)]
+        mode: RenderMode,
+        #[serde(default)]
+        permissions: Option<String>,
+        #[serde(default)]
+        header: bool,
+        #[serde(default)]
+        header_comment: Option<String>,
+        #[serde(default)]
+        namespace: Option<String>,
+        #[serde(default)]
+        privileged: bool,
+        #[serde(default)]
+        verbatim: bool,
+        #[serde(default)]
+        newline: Option<Newline>,
+        #[serde(default)]
+        final_newline: Option<FinalNewline>,
+    },
+}
+
+impl TryFrom<SerializedRenderEntry> for RenderEntry {
+    type Error = String;
+
+    fn try_from(entry: SerializedRenderEntry) -> std::result::Result<Self, Self::Error> {
+        match entry {
+            SerializedRenderEntry::Output(output) => Ok(RenderEntry {
+                output,
+                on_change: None,
+                mode: RenderMode::default(),
+                permissions: None,
+                header: false,
+                header_comment: None,
+                namespace: None,
+                privileged: false,
+                verbatim: false,
+                newline: None,
+                final_newline: None,
+            }),
+            SerializedRenderEntry::Entry {
+                output,
+                on_change,
+                mode,
+                permissions,
+                header,
+                header_comment,
+                namespace,
+                privileged,
+                verbatim,
+                newline,
+                final_newline,
+            } => Ok(RenderEntry {
+                output,
+                on_change,
+                mode,
+                permissions: permissions
+                    .map(|permissions| parse_permissions(&permissions))
+                    .transpose()?,
+                header,
+                header_comment,
+                namespace,
+                privileged,
+                verbatim,
+                newline,
+                final_newline,
+            }),
+        }
+    }
+}
+
+/// Returns the names of all variables referenced by the template `input` in the order of their
+/// first occurrence, including the ones named in `{@require @}` directives. Builtin variables
+/// are not included.
+pub fn referenced_variables(input: &str) -> Result<Vec<String>> {
+    fn collect(tokens: &[Token<'_>], names: &mut Vec<String>) {
+        for token in tokens {
+            let referenced = match token {
+                Token::Variable { name, .. } => vec![*name],
+                Token::Require(required) => required.clone(),
+                Token::If { name, .. } => vec![*name],
+                Token::IfExists { body, .. } => {
+                    collect(body, names);
+                    continue;
+                }
+                _ => continue,
+            };
+            for name in referenced {
+                if !name.is_empty()
+                    && !name.starts_with(BUILTIN_PREFIX)
+                    && !names.iter().any(|n| n == name)
+                {
+                    names.push(name.to_string());
+                }
+            }
+            if let Token::If {
+                body, else_body, ..
+            } = token
+            {
+                collect(body, names);
+                collect(else_body, names);
+            }
+        }
+    }
+    let tokens = parser::parse_input(input).context("parsing input error")?;
+    let mut names = vec![];
+    collect(&tokens, &mut names);
+    Ok(names)
+}
+
+/// A token of a parsed template with its location, see [`parse_with_spans`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TemplateToken {
+    /// Byte offset of the token in the template.
+    pub offset: usize,
+    /// Byte offset just past the end of the token, a line break skipped after a directive is a
+    /// part of it.
+    pub end: usize,
+    /// 1-based line of the token.
+    pub line: usize,
+    /// 1-based column of the token in characters.
+    pub column: usize,
+    #[serde(flatten)]
+    pub kind: TokenKind,
+}
+
+impl TemplateToken {
+    /// Returns the byte range of the token in the template, `&template[token.span()]` is the
+    /// source of the token.
+    pub fn span(&self) -> Range<usize> {
+        self.offset..self.end
+    }
+}
+
+/// What a token of a template is, mirrors the tokens produced by the parser.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TokenKind {
+    /// Text that is output as is, including the content of raw blocks.
+    Text {
+        text: String,
+    },
+    Variable {
+        name: String,
+        filters: Vec<String>,
+        /// The whole tag as written in the template.
+        raw: String,
+    },
+    FileSource {
+        path: String,
+        trim: bool,
+        filters: Vec<String>,
+    },
+    Require {
+        names: Vec<String>,
+    },
+    IfExists {
+        path: String,
+        body: Vec<TemplateToken>,
+    },
+    If {
+        name: String,
+        negated: bool,
+        body: Vec<TemplateToken>,
+        else_body: Vec<TemplateToken>,
+    },
+    Command {
+        command: String,
+    },
+}
+
+impl From<parser::Spanned<'_>> for TemplateToken {
+    fn from(spanned: parser::Spanned<'_>) -> Self {
+        let strings = |values: Vec<&str>| values.into_iter().map(String::from).collect();
+        let kind = match spanned.token {
+            Token::Text(text) => TokenKind::Text { text: text.into() },
+            Token::Variable { name, filters, raw } => TokenKind::Variable {
+                name: name.into(),
+                filters: strings(filters),
+                raw: raw.into(),
+            },
+            Token::FileSource {
+                path,
+                trim,
+                filters,
+            } => TokenKind::FileSource {
+                path: path.into(),
+                trim,
+                filters: strings(filters),
+            },
+            Token::Require(names) => TokenKind::Require {
+                names: strings(names),
+            },
+            Token::IfExists { path, .. } => TokenKind::IfExists {
+                path: path.into(),
+                body: spanned.body.into_iter().map(Into::into).collect(),
+            },
+            Token::If { name, negated, .. } => TokenKind::If {
+                name: name.into(),
+                negated,
+                body: spanned.body.into_iter().map(Into::into).collect(),
+                else_body: spanned.else_body.into_iter().map(Into::into).collect(),
+            },
+            Token::Command(command) => TokenKind::Command {
+                command: command.into(),
+            },
+        };
+        TemplateToken {
+            offset: spanned.offset,
+            end: spanned.end,
+            line: spanned.line,
+            column: spanned.column,
+            kind,
+        }
+    }
+}
+
+/// Parses the template `input` into tokens recording where each of them starts, the front-matter
+/// is not a part of the tokens. Useful to inspect how a template is understood.
+pub fn parse_with_spans(input: &str) -> Result<Vec<TemplateToken>> {
+    let tokens = parser::parse_with_spans(input).context("parsing input error")?;
+    Ok(tokens.into_iter().map(Into::into).collect())
+}
+
+/// Expands a leading `~` of `path` to the home directory and environment variables like
+/// `$XDG_CONFIG_HOME` or `${HOME}`. On Windows `~\` is expanded to the user profile as well as
+/// `%APPDATA%` style variables. Variables that aren't set are left as they are.
+pub fn expand_path(path: &Path) -> PathBuf {
+    let path = path.to_string_lossy();
+    #[cfg(windows)]
+    let path = expand_windows(&path, dirs::home_dir, |var| std::env::var(var).ok());
+    let expanded = shellexpand::full_with_context_no_errors(&path, dirs::home_dir, |var| {
+        std::env::var(var).ok()
+    });
+    PathBuf::from(expanded.as_ref())
+}
+
+/// Expands a leading `~\` to the directory returned by `home_dir` and `%VAR%` references for
+/// which `context` returns a value.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn expand_windows<H, C>(path: &str, home_dir: H, context: C) -> String
+where
+    H: FnOnce() -> Option<PathBuf>,
+    C: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    if let (Some(tail), Some(home)) = (path.strip_prefix("~\\"), home_dir()) {
+        expanded.push_str(&home.to_string_lossy());
+        expanded.push('\\');
+        rest = tail;
+    }
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after
+            .find('%')
+            .filter(|end| *end > 0)
+            .and_then(|end| Some((end, context(&after[..end])?)));
+        match value {
+            Some((end, value)) => {
+                expanded.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                expanded.push('%');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Number of leading bytes of a file that are checked for a NUL byte by [`is_binary`].
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// Checks whether `content` looks like binary data, that is whether there is a NUL byte in its
+/// first 8 KiB.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(BINARY_SNIFF_LEN).any(|b| *b == 0)
+}
+
+/// Checks whether the file at `path` looks binary like [`is_binary`], only the start of the
+/// file is read.
+pub fn is_binary_file(path: &Path) -> io::Result<bool> {
+    use std::io::Read;
+    let mut start = Vec::with_capacity(BINARY_SNIFF_LEN);
+    std::fs::File::open(path)?
+        .take(BINARY_SNIFF_LEN as u64)
+        .read_to_end(&mut start)?;
+    Ok(is_binary(&start))
+}
+
+/// Checks whether `content` is UTF-8 text that can be rendered, that is valid UTF-8 which
+/// doesn't look binary.
+pub fn is_text(content: &[u8]) -> bool {
+    !is_binary(content) && std::str::from_utf8(content).is_ok()
+}
+
+/// Checks whether the file at `path` is text like [`is_text`], templates that aren't can only be
+/// copied verbatim.
+pub fn is_text_file(path: &Path) -> io::Result<bool> {
+    Ok(is_text(&std::fs::read(path)?))
+}
+
+/// Parses file permissions in octal notation like `0600` or `644`.
+pub fn parse_permissions(permissions: &str) -> std::result::Result<u32, String> {
+    u32::from_str_radix(permissions.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("invalid permissions `{}`", permissions))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "SerializedRenderEntry")]
+pub struct RenderEntry {
+    output: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on_change: Option<String>,
+    mode: RenderMode,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_permissions"
+    )]
+    permissions: Option<u32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    header: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    header_comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    privileged: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    verbatim: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    newline: Option<Newline>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    final_newline: Option<FinalNewline>,
+}
+
+fn serialize_permissions<S: serde::Serializer>(
+    permissions: &Option<u32>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match permissions {
+        Some(permissions) => serializer.serialize_str(&format!("{:04o}", permissions)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Specifies how the rendered template ends up at the output path.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderMode {
+    /// The rendered template is written to the output path.
+    #[default]
+    Copy,
+    /// The rendered template is written to a directory managed by mold and the output path is
+    /// a symlink to it.
+    Symlink,
+}
+
+/// Line endings of the rendered content.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Newline {
+    /// Line endings are left as they are.
+    #[default]
+    Keep,
+    Lf,
+    Crlf,
+    /// `crlf` on Windows and `lf` everywhere else.
+    Native,
+}
+
+impl Newline {
+    /// Converts every line ending of `content` to this one. Only `\n` and `\r\n` are line
+    /// endings, a final line without one is left without one.
+    pub fn apply(self, content: &str) -> String {
+        let lf = || content.replace("\r\n", "\n");
+        match self {
+            Newline::Keep => content.to_string(),
+            Newline::Lf => lf(),
+            Newline::Crlf => lf().replace('\n', "\r\n"),
+            Newline::Native if cfg!(windows) => Newline::Crlf.apply(content),
+            Newline::Native => Newline::Lf.apply(content),
+        }
+    }
+}
+
+impl std::str::FromStr for Newline {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Newline::Keep),
+            "lf" => Ok(Newline::Lf),
+            "crlf" => Ok(Newline::Crlf),
+            "native" => Ok(Newline::Native),
+            _ => Err(format!(
+                "invalid newline `{}`, expected one of: lf, crlf, native, keep",
+                s
+            )),
+        }
+    }
+}
+
+/// Line breaks at the end of the rendered content.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinalNewline {
+    /// The end of the content is left as it is.
+    #[default]
+    Keep,
+    /// The content ends with exactly one line break.
+    Always,
+    /// The content doesn't end with a line break.
+    Never,
+}
+
+impl FinalNewline {
+    /// Adjusts the line breaks at the end of `content`. An added line break is `\r\n` if the
+    /// removed one was, or if there was none and the content uses `\r\n`. Empty content is left
+    /// empty.
+    pub fn apply(self, content: &str) -> String {
+        let trimmed = content.trim_end_matches(['\r', '\n']);
+        match self {
+            FinalNewline::Keep => content.to_string(),
+            FinalNewline::Never => trimmed.to_string(),
+            FinalNewline::Always if content.is_empty() => String::new(),
+            FinalNewline::Always => {
+                let trailing = &content[trimmed.len()..];
+                let crlf = trailing.starts_with("\r\n")
+                    || (trailing.is_empty() && content.contains("\r\n"));
+                format!("{}{}", trimmed, if crlf { "\r\n" } else { "\n" })
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for FinalNewline {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(FinalNewline::Keep),
+            "always" => Ok(FinalNewline::Always),
+            "never" => Ok(FinalNewline::Never),
+            _ => Err(format!(
+                "invalid final newline `{}`, expected one of: always, never, keep",
+                s
+            )),
+        }
+    }
+}
+
+impl RenderEntry {
+    /// Location where the rendered template is saved.
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// Shell command to run after the content of the output changed.
+    pub fn on_change(&self) -> Option<&str> {
+        self.on_change.as_deref()
+    }
+
+    pub fn mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    /// Permissions of the output file, if not specified the permissions of an existing output
+    /// are kept.
+    pub fn permissions(&self) -> Option<u32> {
+        self.permissions
+    }
+
+    /// Whether a header marking the output as generated is prepended to it. Setting the comment
+    /// leader of the header enables it as well.
+    pub fn header(&self) -> bool {
+        self.header || self.header_comment.is_some()
+    }
+
+    /// Comment leader used for the header, if not specified it is derived from the extension of
+    /// the output.
+    pub fn header_comment(&self) -> Option<&str> {
+        self.header_comment.as_deref()
+    }
+
+    /// Namespace this entry is always rendered with regardless of the selected one.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Whether the output is written with elevated privileges, like a file in `/etc`.
+    pub fn privileged(&self) -> bool {
+        self.privileged
+    }
+
+    /// Whether the template is copied to the output as is without being rendered, like a static
+    /// or binary file.
+    pub fn verbatim(&self) -> bool {
+        self.verbatim
+    }
+
+    /// Line endings the rendered content is converted to, if not specified the one passed on
+    /// the command line is used.
+    pub fn newline(&self) -> Option<Newline> {
+        self.newline
+    }
+
+    /// Whether the rendered content ends with a line break, if not specified the option passed
+    /// on the command line is used.
+    pub fn final_newline(&self) -> Option<FinalNewline> {
+        self.final_newline
+    }
+}
+
+/// A template can be rendered to a single output or to a list of outputs.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+enum SerializedRenderTargets {
+    Single(RenderEntry),
+    Multiple(Vec<RenderEntry>),
+}
+
+impl From<SerializedRenderTargets> for Vec<RenderEntry> {
+    fn from(targets: SerializedRenderTargets) -> Self {
+        match targets {
+            SerializedRenderTargets::Single(entry) => vec![entry],
+            SerializedRenderTargets::Multiple(entries) => entries,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Hooks {
+    #[serde(default)]
+    post_render_all: Option<String>,
+}
+
+impl Hooks {
+    /// Shell command to run once after all renders of the context are finished.
+    pub fn post_render_all(&self) -> Option<&str> {
+        self.post_render_all.as_deref()
+    }
+}
+
+/// What to do when a variable value references an environment variable that is not set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum MissingEnv {
+    #[default]
+    Error,
+    /// The reference is replaced with an empty string.
+    Empty,
+}
+
+/// Settings for expanding `${VAR}` references to environment variables in variable values when
+/// the context is loaded.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct EnvExpansion {
+    #[serde(default)]
+    expand: bool,
+    #[serde(default)]
+    missing: MissingEnv,
+}
+
+const ENV_START_TAG: &str = "${";
+const ENV_END_TAG: char = '}';
+
+/// Replaces all `${VAR}` references in `value` with the value of the environment variable.
+fn expand_env(value: &str, missing: MissingEnv) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find(ENV_START_TAG) {
+        let name_start = start + ENV_START_TAG.len();
+        let name_end = match rest[name_start..].find(ENV_END_TAG) {
+            Some(len) => name_start + len,
+            None => break,
+        };
+        expanded.push_str(&rest[..start]);
+        let name = &rest[name_start..name_end];
+        match std::env::var(name) {
+            Ok(value) => expanded.push_str(&value),
+            Err(_) if missing == MissingEnv::Empty => {}
+            Err(e) => {
+                return Err(anyhow!(
+                    "failed to read environment variable `{}` - {}",
+                    name,
+                    e
+                ))
+            }
+        }
+        rest = &rest[name_end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Parses the content of a dotenv file. Lines are `KEY=VALUE` pairs optionally prefixed with
+/// `export`, values can be single quoted to be taken literally or double quoted to allow escape
+/// sequences. Comments start with `#`.
+fn parse_env_file(content: &str) -> Result<HashMap<VariableKey, VariableValue>> {
+    let mut variables = HashMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected `KEY=VALUE` at line {}", i + 1))?;
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            quoted
+                .find('\'')
+                .map(|end| quoted[..end].to_string())
+                .ok_or_else(|| anyhow!("unterminated single quote at line {}", i + 1))?
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            let mut unquoted = String::new();
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => unquoted.push('\n'),
+                        Some('t') => unquoted.push('\t'),
+                        Some(c) => unquoted.push(c),
+                        None => {}
+                    },
+                    Some(c) => unquoted.push(c),
+                    None => return Err(anyhow!("unterminated double quote at line {}", i + 1)),
+                }
+            }
+            unquoted
+        } else {
+            // an unquoted value ends at a comment
+            match value.find(" #") {
+                Some(pos) => value[..pos].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        variables.insert(key.trim().to_string(), value);
     }
+    Ok(variables)
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct SerializedContext {
-    #[serde(default = "Namespace::global")]
-    global: Namespace,
+    /// Version of the context file format.
+    #[serde(default = "default_context_version")]
+    version: u32,
+    /// Global namespace, a namespace named `GLOBAL` in `namespaces` takes its place.
+    #[serde(default)]
+    global: Option<Namespace>,
+    #[serde(default)]
+    renders: IndexMap<PathBuf, SerializedRenderTargets>,
+    /// Variables added to every namespace that doesn't define them itself.
+    #[serde(default)]
+    defaults: HashMap<VariableKey, VariableValue>,
     #[serde(default)]
-    renders: HashMap<PathBuf, PathBuf>,
+    hooks: Hooks,
+    #[serde(default)]
+    env: EnvExpansion,
+    /// Directories searched for file sources.
+    #[serde(default)]
+    include_dirs: Vec<PathBuf>,
+    /// Namespace used when none is specified on the command line.
+    #[serde(default)]
+    default_namespace: Option<String>,
     namespaces: Vec<Namespace>,
 }
 
-impl From<SerializedContext> for Context {
-    fn from(ctx: SerializedContext) -> Self {
-        let mut namespaces: HashMap<String, Namespace> = ctx
-            .namespaces
+impl TryFrom<SerializedContext> for Context {
+    type Error = anyhow::Error;
+
+    fn try_from(ctx: SerializedContext) -> Result<Self> {
+        let mut namespaces: IndexMap<String, Namespace> = IndexMap::new();
+        let mut duplicates = vec![];
+        for namespace in ctx.namespaces {
+            if namespaces.contains_key(&namespace.name) {
+                if !duplicates.contains(&namespace.name) {
+                    duplicates.push(namespace.name);
+                }
+                continue;
+            }
+            namespaces.insert(namespace.name.clone(), namespace);
+        }
+        if !duplicates.is_empty() {
+            return Err(anyhow!(
+                "namespaces defined more than once: {}",
+                duplicates.join(", ")
+            ));
+        }
+        let mut global = match (namespaces.shift_remove(GLOBAL_NS), ctx.global) {
+            (Some(global), Some(_)) => {
+                warn!(
+                    "namespace `{}` in `namespaces` replaces the `global` section",
+                    GLOBAL_NS
+                );
+                global
+            }
+            (Some(global), None) => global,
+            (None, global) => global.unwrap_or_else(Namespace::global),
+        };
+        if ctx.env.expand {
+            global.expand_env(ctx.env.missing)?;
+            for namespace in namespaces.values_mut() {
+                namespace
+                    .expand_env(ctx.env.missing)
+                    .with_context(|| format!("in namespace `{}`", namespace.name))?;
+            }
+        }
+        global.load_env_file()?;
+        for namespace in namespaces.values_mut() {
+            namespace
+                .load_env_file()
+                .with_context(|| format!("in namespace `{}`", namespace.name))?;
+        }
+        let mut defaults = Namespace {
+            name: "defaults".into(),
+            variables: ctx.defaults,
+            ..Default::default()
+        };
+        if ctx.env.expand {
+            defaults
+                .expand_env(ctx.env.missing)
+                .context("in defaults")?;
+        }
+        let renders = ctx
+            .renders
             .into_iter()
-            .map(|ns| (ns.name.clone(), ns))
+            .map(|(template, targets)| (template, targets.into()))
             .collect();
-        let global = if let Some(global) = namespaces.remove(GLOBAL_NS) {
-            global
-        } else {
-            ctx.global
-        };
-        Context {
+        Ok(Context {
             global,
-            renders: ctx.renders,
+            renders,
+            defaults: defaults.variables,
+            hooks: ctx.hooks,
             namespaces,
-        }
+            default_namespace: ctx.default_namespace,
+            ignore_namespace_case: false,
+        })
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Context {
     global: Namespace,
-    renders: HashMap<PathBuf, PathBuf>,
-    namespaces: HashMap<String, Namespace>,
+    renders: IndexMap<PathBuf, Vec<RenderEntry>>,
+    /// Variables added to the namespaces by [`Context::finish`].
+    defaults: HashMap<VariableKey, VariableValue>,
+    hooks: Hooks,
+    namespaces: IndexMap<String, Namespace>,
+    default_namespace: Option<String>,
+    ignore_namespace_case: bool,
 }
 
 #[allow(dead_code)]
 impl Context {
+    /// Returns the name under which `namespace` is defined in the context. Without an exact match
+    /// names are compared case-insensitively if enabled.
+    fn namespace_name<'a>(&'a self, namespace: &'a str) -> Option<&'a str> {
+        if let Some((name, _)) = self.namespaces.get_key_value(namespace) {
+            return Some(name);
+        }
+        if !self.ignore_namespace_case {
+            return None;
+        }
+        let lowercase = namespace.to_lowercase();
+        self.namespaces
+            .keys()
+            .find(|name| name.to_lowercase() == lowercase)
+            .map(String::as_str)
+    }
+
     fn get_namespace(&self, namespace: &str) -> Option<&Namespace> {
-        self.namespaces.get(namespace)
+        self.namespaces.get(self.namespace_name(namespace)?)
     }
 
     fn global(&self) -> &Namespace {
@@ -77,96 +994,2249 @@ impl Context {
         self.global.variables.get(key)
     }
 
-    fn get_variable_value(&self, key: &str, namespace: &str) -> Option<&VariableValue> {
-        self.namespaces
-            .get(namespace)
+    /// Returns the raw value of the variable `key` in `namespace` falling back to the global
+    /// namespace, nested variables are not rendered.
+    pub fn get_variable_value(&self, key: &str, namespace: &str) -> Option<&VariableValue> {
+        self.get_namespace(namespace)
             .and_then(|ns| ns.variables.get(key))
             .or_else(|| self.get_global_variable(key))
     }
 
-    pub fn renders(&self) -> &HashMap<PathBuf, PathBuf> {
-        &self.renders
+    /// Returns the names of variables defined in any namespace that only differ from `key` in
+    /// case or in `_` and `-`, in alphabetical order.
+    fn similar_variables(&self, key: &str) -> Vec<&str> {
+        let normalize = |key: &str| key.to_lowercase().replace('-', "_");
+        let normalized = normalize(key);
+        let mut similar: Vec<_> = std::iter::once(&self.global)
+            .chain(self.namespaces.values())
+            .flat_map(|namespace| namespace.variables.keys())
+            .filter(|name| *name != key && normalize(name) == normalized)
+            .map(String::as_str)
+            .collect();
+        similar.sort_unstable();
+        similar.dedup();
+        similar
     }
-}
 
-#[derive(Debug, Default)]
-pub struct Mold {
-    context: Context,
-}
+    /// Returns the namespace `name`, `GLOBAL` returns the global namespace.
+    pub fn namespace(&self, name: &str) -> Option<&Namespace> {
+        if self.is_global(name) {
+            return Some(&self.global);
+        }
+        self.get_namespace(name)
+    }
 
-impl Mold {
-    pub fn new(context_file: &std::path::Path) -> Result<Self> {
-        let data = std::fs::read(context_file).context("failed to read context file")?;
-        serde_yaml::from_slice::<SerializedContext>(&data)
-            .map(|ctx| Mold {
-                context: ctx.into(),
-            })
-            .context("context deserialization error")
+    /// Returns all namespaces except the global one in alphabetical order of their names.
+    pub fn namespaces(&self) -> impl Iterator<Item = &Namespace> {
+        let mut namespaces: Vec<_> = self.namespaces.values().collect();
+        namespaces.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        namespaces.into_iter()
     }
 
-    pub fn context(&self) -> &Context {
-        &self.context
+    /// Returns the names of all namespaces except the global one in alphabetical order.
+    pub fn namespace_names(&self) -> Vec<&str> {
+        let mut namespaces: Vec<_> = self.namespaces.keys().map(String::as_str).collect();
+        namespaces.sort_unstable();
+        namespaces
     }
 
-    pub fn render(&self, input: &str, namespace: Option<&str>, render_raw: bool) -> Result<String> {
-        let mut out = String::new();
-        let tokens = parser::parse_input(input).context("parsing input error")?;
-        for token in tokens {
-            match token {
-                Token::Text(t) => out.push_str(t),
-                Token::Variable { name, raw } => {
-                    let rendered = if let Some(ns) = namespace {
-                        if let Some(value) = self.context.get_variable_value(name, ns) {
-                            // try to render variable in case it contains nested variables
-                            if let Ok(rendered) = self.render(value.as_str(), namespace, render_raw)
-                            {
-                                out.push_str(&rendered);
-                            } else {
-                                out.push_str(value);
-                            }
-                            true
-                        } else {
-                            false
-                        }
-                    } else {
-                        // try to use variables from global namespace
-                        if let Some(value) = self.context.get_global_variable(name) {
-                            if let Ok(rendered) = self.render(value.as_str(), namespace, render_raw)
-                            {
-                                out.push_str(&rendered);
-                            } else {
-                                out.push_str(value);
-                            }
-                            true
-                        } else {
-                            false
-                        }
-                    };
-                    if !rendered && render_raw {
-                        out.push_str(raw);
+    fn is_global(&self, namespace: &str) -> bool {
+        namespace == GLOBAL_NS
+            || (self.ignore_namespace_case && namespace.eq_ignore_ascii_case(GLOBAL_NS))
+    }
+
+    pub fn has_namespace(&self, namespace: &str) -> bool {
+        self.is_global(namespace) || self.namespace_name(namespace).is_some()
+    }
+
+    /// Makes namespace lookups match names case-insensitively if there is no namespace with the
+    /// exact name. Variable names stay case-sensitive.
+    pub fn set_ignore_namespace_case(&mut self, ignore: bool) {
+        self.ignore_namespace_case = ignore;
+    }
+
+    /// Sets the variable `key` to `value` in `namespace` overriding an existing value, a
+    /// namespace that doesn't exist is created. If no namespace is specified the variable is set
+    /// in the global namespace and overrides the values of all other namespaces as well.
+    pub fn set_variable(&mut self, namespace: Option<&str>, key: &str, value: &str) {
+        match namespace.filter(|namespace| !self.is_global(namespace)) {
+            Some(namespace) => {
+                let namespace = self
+                    .namespace_name(namespace)
+                    .unwrap_or(namespace)
+                    .to_string();
+                self.namespaces
+                    .entry(namespace.clone())
+                    .or_insert_with(|| Namespace {
+                        name: namespace,
+                        ..Default::default()
+                    })
+                    .variables
+                    .insert(key.to_string(), value.to_string());
+            }
+            None => {
+                for namespace in self.namespaces.values_mut() {
+                    if let Some(current) = namespace.variables.get_mut(key) {
+                        *current = value.to_string();
                     }
                 }
-                Token::FileSource { path, trim } => {
-                    let content =
-                        std::fs::read_to_string(path).context("failed to read source file")?;
+                self.global
+                    .variables
+                    .insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    /// Returns all outputs of every template in the context in the order of the context file.
+    pub fn renders(&self) -> &IndexMap<PathBuf, Vec<RenderEntry>> {
+        &self.renders
+    }
 
-                    let content_processed = if trim { content.trim() } else { &content };
+    /// Returns every template paired with each of its outputs.
+    pub fn render_entries(&self) -> impl Iterator<Item = (&Path, &RenderEntry)> {
+        self.renders.iter().flat_map(|(template, entries)| {
+            entries.iter().map(move |entry| (template.as_path(), entry))
+        })
+    }
 
-                    out.push_str(content_processed);
-                }
+    /// Expands `~` and environment variables in the templates and outputs of `renders` with
+    /// [`expand_path`], templates that expand to the same path are merged.
+    fn expand_render_paths(&mut self) {
+        for (template, mut entries) in std::mem::take(&mut self.renders) {
+            for entry in &mut entries {
+                entry.output = expand_path(&entry.output);
             }
+            self.renders
+                .entry(expand_path(&template))
+                .or_default()
+                .extend(entries);
         }
+    }
 
-        Ok(out)
+    /// Joins the relative templates of `renders` to `dir`, templates that end up with the same
+    /// path are merged.
+    fn join_relative_templates(&mut self, dir: &Path) {
+        for (template, entries) in std::mem::take(&mut self.renders) {
+            let template = match template.is_relative() {
+                true => dir.join(template),
+                false => template,
+            };
+            self.renders.entry(template).or_default().extend(entries);
+        }
     }
 
-    pub fn render_file(
-        &self,
-        file: &std::path::Path,
-        namespace: Option<&str>,
-        render_raw: bool,
-    ) -> Result<String> {
-        let input = std::fs::read_to_string(file).context("render file read error")?;
-        self.render(&input, namespace, render_raw)
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    /// Returns the namespace used when none is specified on the command line.
+    pub fn default_namespace(&self) -> Option<&str> {
+        self.default_namespace.as_deref()
+    }
+
+    /// Merges `other` into this context with the values of `other` taking precedence. Variables
+    /// are overridden per namespace and namespaces missing here are added. The `renders` are
+    /// unioned, the outputs of a template present in both contexts are replaced with the ones of
+    /// `other` and the defaults are overridden per variable. A hook or the default namespace of
+    /// `other` replaces the one of this context.
+    pub fn merge(&mut self, other: Context) {
+        self.global.variables.extend(other.global.variables);
+        self.global.secrets.extend(other.global.secrets);
+        for (name, namespace) in other.namespaces {
+            match self.namespaces.get_mut(&name) {
+                Some(current) => {
+                    current.variables.extend(namespace.variables);
+                    current.secrets.extend(namespace.secrets);
+                }
+                None => {
+                    self.namespaces.insert(name, namespace);
+                }
+            }
+        }
+        self.renders.extend(other.renders);
+        self.defaults.extend(other.defaults);
+        if other.hooks.post_render_all.is_some() {
+            self.hooks.post_render_all = other.hooks.post_render_all;
+        }
+        if other.default_namespace.is_some() {
+            self.default_namespace = other.default_namespace;
+        }
+    }
+
+    /// Adds the defaults to every namespace that doesn't define them itself and checks that the
+    /// namespaces used by render entries and the default namespace are defined. Runs once all
+    /// context files are merged, so the defaults reach namespaces added by a later file and a
+    /// render entry can use a namespace of another file.
+    fn finish(&mut self) -> Result<()> {
+        for namespace in self.namespaces.values_mut() {
+            for (key, value) in &self.defaults {
+                namespace
+                    .variables
+                    .entry(key.clone())
+                    .or_insert_with(|| value.clone());
+            }
+        }
+        for (template, entry) in self.render_entries() {
+            if let Some(namespace) = entry.namespace().filter(|ns| !self.has_namespace(ns)) {
+                return Err(anyhow!(
+                    "render entry `{}` uses namespace `{}` which is not defined",
+                    template.display(),
+                    namespace
+                ));
+            }
+        }
+        if let Some(namespace) = self
+            .default_namespace
+            .as_deref()
+            .filter(|ns| !self.has_namespace(ns))
+        {
+            return Err(anyhow!("default namespace `{}` is not defined", namespace));
+        }
+        Ok(())
+    }
+
+    /// Returns all variables that are available when rendering with `namespace`, that is
+    /// the variables of the namespace with missing ones taken from the global namespace. If no
+    /// namespace is specified only the global variables are returned. Returns `None` if the
+    /// namespace doesn't exist.
+    pub fn resolved_variables(
+        &self,
+        namespace: Option<&str>,
+    ) -> Option<BTreeMap<&VariableKey, &VariableValue>> {
+        let mut variables: BTreeMap<_, _> = self.global.variables.iter().collect();
+        if let Some(namespace) = namespace.filter(|namespace| !self.is_global(namespace)) {
+            variables.extend(self.get_namespace(namespace)?.variables.iter());
+        }
+        Some(variables)
+    }
+}
+
+/// Output and namespace declared by a template in a front-matter block like
+/// `{@meta output = "~/.config/foo.conf", namespace = "dark" @}` at the top of the template.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateMeta {
+    output: Option<PathBuf>,
+    namespace: Option<String>,
+}
+
+impl TemplateMeta {
+    /// Parses the front-matter of `input`, a template without front-matter has no declarations.
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut meta = TemplateMeta::default();
+        let (entries, _) = parser::parse_meta(input).context("parsing meta error")?;
+        for (key, value) in entries.unwrap_or_default() {
+            match key {
+                "output" => meta.output = Some(PathBuf::from(value)),
+                "namespace" => meta.namespace = Some(value),
+                _ => {}
+            }
+        }
+        Ok(meta)
+    }
+
+    pub fn from_file(file: &Path) -> Result<Self> {
+        let input = std::fs::read_to_string(file)
+            .map(strip_bom)
+            .with_context(|| format!("failed to read template `{}`", file.display()))?;
+        Self::parse(&input)
+            .with_context(|| format!("failed to parse front-matter of `{}`", file.display()))
+    }
+
+    /// Location where the rendered template should be saved.
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    /// Namespace used for rendering the template.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+}
+
+/// Options of a single render that are shared with the nested renders of variable values.
+#[derive(Debug, Clone, Copy)]
+struct RenderScope<'a> {
+    namespace: Option<&'a str>,
+    render_raw: bool,
+    /// Directory of the rendered template, relative file sources are looked up in it first.
+    dir: Option<&'a Path>,
+    /// Collects the names of variables without a value.
+    missing: Option<&'a RefCell<Vec<String>>>,
+    /// Files that are currently being rendered starting with the template.
+    includes: &'a [Include],
+    /// Variables whose values are currently being rendered, the outermost first.
+    variables: &'a [&'a str],
+}
+
+impl<'a> RenderScope<'a> {
+    fn new(namespace: Option<&'a str>, render_raw: bool) -> Self {
+        Self {
+            namespace,
+            render_raw,
+            dir: None,
+            missing: None,
+            includes: &[],
+            variables: &[],
+        }
+    }
+
+    /// Records `name` as a variable without a value.
+    fn record_missing(&self, name: &str) {
+        if let Some(missing) = self.missing {
+            let mut missing = missing.borrow_mut();
+            if !missing.iter().any(|missing| missing == name) {
+                missing.push(name.to_string());
+            }
+        }
+    }
+}
+
+/// Text displayed in place of the values of secret variables.
+pub const REDACTED: &str = "****";
+
+/// Values of secret variables shorter than this are only redacted where they aren't a part of a
+/// longer word, replacing them everywhere would hide unrelated text that happens to contain them.
+pub const MIN_SECRET_LEN: usize = 6;
+
+/// Values of the secret variables of one render, see [`Mold::secrets`].
+#[derive(Debug, Clone, Default)]
+pub struct Secrets {
+    /// Longer values come first, so a value containing another one is replaced as a whole.
+    values: Vec<String>,
+}
+
+impl Secrets {
+    /// Replaces every secret value in `text` with `****`, used to display rendered content
+    /// without revealing the secrets.
+    pub fn redact(&self, text: &str) -> String {
+        self.values.iter().fold(text.to_string(), |text, value| {
+            if value.chars().count() < MIN_SECRET_LEN {
+                redact_words(&text, value)
+            } else {
+                text.replace(value.as_str(), REDACTED)
+            }
+        })
+    }
+}
+
+/// Replaces occurrences of `value` in `text` that are neither preceded nor followed by a letter,
+/// digit or underscore.
+fn redact_words(text: &str, value: &str) -> String {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(value) {
+        let before = rest[..pos]
+            .chars()
+            .next_back()
+            .or_else(|| redacted.chars().next_back());
+        let after = rest[pos + value.len()..].chars().next();
+        if is_word(before) || is_word(after) {
+            // an occurrence starting within this one may still be a whole word
+            let skip = pos + value.chars().next().map_or(1, char::len_utf8);
+            redacted.push_str(&rest[..skip]);
+            rest = &rest[skip..];
+        } else {
+            redacted.push_str(&rest[..pos]);
+            redacted.push_str(REDACTED);
+            rest = &rest[pos + value.len()..];
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Default limit of the number of files rendered within each other.
+pub const DEFAULT_MAX_INCLUDE_DEPTH: usize = 32;
+
+/// Default time after which a command in a `{! !}` tag is killed.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A file that is currently being rendered.
+#[derive(Debug, Clone)]
+struct Include {
+    path: PathBuf,
+    canonical: PathBuf,
+}
+
+fn display_includes(includes: &[Include]) -> String {
+    includes
+        .iter()
+        .map(|include| include.path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// How a variable resolves when rendering, see [`Mold::explain_variable`].
+#[derive(Debug, Clone, Default)]
+pub struct VariableExplanation {
+    pub name: String,
+    /// Raw value defined in the requested namespace, `None` without a namespace.
+    pub namespace_value: Option<VariableValue>,
+    /// Raw value defined in the global namespace.
+    pub global_value: Option<VariableValue>,
+    /// Value of the builtin variable if it isn't defined in the context.
+    pub builtin: Option<String>,
+    /// Explanations of the variables referenced by the raw value.
+    pub nested: Vec<VariableExplanation>,
+    /// The variable references itself through the raw value, it is not expanded any further.
+    pub cycle: bool,
+    /// Final value with nested variables rendered, `None` if the variable is not defined or
+    /// references itself.
+    pub value: Option<String>,
+    /// Variables that only differ in case or in `_` and `-`, looked up if it isn't defined.
+    pub similar: Vec<String>,
+}
+
+impl VariableExplanation {
+    /// Returns the raw value used for rendering.
+    pub fn raw(&self) -> Option<&str> {
+        self.namespace_value
+            .as_deref()
+            .or(self.global_value.as_deref())
+    }
+
+    /// Returns true if the variable or any variable it references references itself.
+    pub fn has_cycle(&self) -> bool {
+        self.cycle || self.nested.iter().any(VariableExplanation::has_cycle)
+    }
+}
+
+/// Output of a render together with the names of variables that had no value.
+#[derive(Debug, Clone, Default)]
+pub struct Rendered {
+    pub output: String,
+    /// Names of the missing variables in the order they were first encountered, including the
+    /// ones referenced by values of other variables.
+    pub missing: Vec<String>,
+}
+
+/// Replaced with the name of the variable in the placeholder of missing variables.
+const MISSING_NAME_TAG: &str = "{name}";
+
+/// Prefix of the builtin variables like `mold.hostname`.
+const BUILTIN_PREFIX: &str = "mold.";
+
+#[derive(Debug)]
+pub struct Mold {
+    context: Context,
+    context_file: Option<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    template_root: Option<PathBuf>,
+    missing_placeholder: Option<String>,
+    max_include_depth: usize,
+    builtins_enabled: bool,
+    nested_render: bool,
+    commands_allowed: bool,
+    command_timeout: Duration,
+    source: Box<dyn TemplateSource>,
+}
+
+impl Default for Mold {
+    fn default() -> Self {
+        Self {
+            context: Context::default(),
+            context_file: None,
+            include_dirs: vec![],
+            template_root: None,
+            missing_placeholder: None,
+            max_include_depth: DEFAULT_MAX_INCLUDE_DEPTH,
+            builtins_enabled: true,
+            nested_render: true,
+            commands_allowed: false,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            source: Box::new(FileSystemSource),
+        }
+    }
+}
+
+impl Mold {
+    /// Loads the context file, a warning is logged if the file declares a version of the format
+    /// that isn't understood. `~` and environment variables in the paths of `renders` are
+    /// expanded with [`expand_path`].
+    pub fn new(context_file: &std::path::Path) -> Result<Self> {
+        Self::load(context_file, false, true)?.finished()
+    }
+
+    /// Loads the context file like [`Mold::new`] but fails if the file declares a version of the
+    /// format that isn't understood.
+    pub fn new_strict(context_file: &std::path::Path) -> Result<Self> {
+        Self::load(context_file, true, true)?.finished()
+    }
+
+    /// Loads the context file like [`Mold::new`] but leaves the paths of `renders` as they are
+    /// written in the file.
+    pub fn new_unexpanded(context_file: &std::path::Path) -> Result<Self> {
+        Self::load(context_file, false, false)?.finished()
+    }
+
+    /// Loads the context files like [`Mold::new`], or [`Mold::new_strict`] if `strict` is set,
+    /// and merges them from left to right with later files overriding the earlier ones. The
+    /// defaults are added and the namespaces are checked once all files are merged, ignoring
+    /// their case if `ignore_namespace_case` is set.
+    pub fn new_merged(
+        context_files: &[PathBuf],
+        strict: bool,
+        ignore_namespace_case: bool,
+    ) -> Result<Self> {
+        let mut merged: Option<Mold> = None;
+        for file in context_files {
+            let mold = match Self::load(file, strict, true) {
+                Err(e) if context_files.len() > 1 => {
+                    return Err(e.context(format!("in context file `{}`", file.display())))
+                }
+                loaded => loaded?,
+            };
+            match &mut merged {
+                Some(merged) => merged.merge(mold),
+                None => merged = Some(mold),
+            }
+        }
+        let mut merged = merged.ok_or_else(|| anyhow!("no context file specified"))?;
+        merged
+            .context
+            .set_ignore_namespace_case(ignore_namespace_case);
+        merged.finished()
+    }
+
+    fn finished(mut self) -> Result<Self> {
+        self.context.finish()?;
+        Ok(self)
+    }
+
+    fn load(context_file: &std::path::Path, strict: bool, expand_paths: bool) -> Result<Self> {
+        let data = std::fs::read(context_file)
+            .with_context(|| format!("failed to read context file `{}`", context_file.display()))?;
+        // the error of serde_yaml already points at the line and column
+        let mut ctx = serde_yaml::from_slice::<SerializedContext>(&data).with_context(|| {
+            format!("failed to parse context file `{}`", context_file.display())
+        })?;
+        if !(1..=CONTEXT_VERSION).contains(&ctx.version) {
+            let message = format!(
+                "context file `{}` has version {} but only versions up to {} are supported",
+                context_file.display(),
+                ctx.version,
+                CONTEXT_VERSION
+            );
+            if strict {
+                return Err(anyhow!(message));
+            }
+            warn!("{}, unknown fields are ignored", message);
+        }
+        // relative include directories and env files are relative to the context file
+        let context_dir = context_file.parent().unwrap_or_else(|| Path::new(""));
+        let resolve = |path: &Path| context_dir.join(expand_path(path));
+        let include_dirs = std::mem::take(&mut ctx.include_dirs)
+            .iter()
+            .map(|dir| resolve(dir))
+            .collect();
+        for namespace in ctx.namespaces.iter_mut().chain(ctx.global.iter_mut()) {
+            if let Some(path) = &mut namespace.from_env_file {
+                *path = resolve(path);
+            }
+        }
+        let mut context: Context = ctx.try_into()?;
+        if expand_paths {
+            context.expand_render_paths();
+        }
+        let mold = Mold {
+            context,
+            context_file: Some(context_file.to_path_buf()),
+            include_dirs,
+            ..Default::default()
+        };
+        debug!(
+            "loaded context from `{}` with {} namespaces and {} renders",
+            context_file.display(),
+            mold.context.namespaces.len(),
+            mold.context.render_entries().count()
+        );
+        Ok(mold)
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Merges the context of `other` into this one with [`Context::merge`] and appends its
+    /// include directories, used to layer context files on top of each other. Relative templates
+    /// of a context file in another directory are joined to its directory, so they don't move.
+    fn merge(&mut self, mut other: Mold) {
+        if let Some(dir) = other
+            .context_dir()
+            .filter(|dir| Some(*dir) != self.context_dir())
+            .map(|dir| std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf()))
+        {
+            other.context.join_relative_templates(&dir);
+        }
+        self.context.merge(other.context);
+        self.include_dirs.extend(other.include_dirs);
+    }
+
+    /// Directories in which relative paths of file sources are looked up after the directory of
+    /// the template.
+    pub fn include_dirs(&self) -> &[PathBuf] {
+        &self.include_dirs
+    }
+
+    /// Sets the directory against which relative template paths are resolved instead of the
+    /// current directory. Relative paths of file sources that are not found next to the template
+    /// or in the include directories are looked up in it as well.
+    pub fn set_template_root(&mut self, root: impl Into<PathBuf>) {
+        self.template_root = Some(root.into());
+    }
+
+    pub fn template_root(&self) -> Option<&Path> {
+        self.template_root.as_deref()
+    }
+
+    /// Returns `path` joined to the template root if it's relative and a root is set, otherwise
+    /// the path is returned unchanged.
+    pub fn resolve_template(&self, path: &Path) -> PathBuf {
+        match &self.template_root {
+            Some(root) if path.is_relative() => root.join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// Directory of the context file the context was loaded from.
+    pub fn context_dir(&self) -> Option<&Path> {
+        self.context_file.as_deref().and_then(Path::parent)
+    }
+
+    /// Resolves the template `path` of an entry of `renders`. A relative path is joined to the
+    /// template root if one is set and otherwise to the directory of the context file.
+    pub fn resolve_render_template(&self, path: &Path) -> PathBuf {
+        match self.context_dir() {
+            Some(dir) if self.template_root.is_none() && path.is_relative() => dir.join(path),
+            _ => self.resolve_template(path),
+        }
+    }
+
+    /// Returns every template of `renders` resolved with [`Mold::resolve_render_template`] paired
+    /// with each of its outputs.
+    pub fn render_entries(&self) -> impl Iterator<Item = (PathBuf, &RenderEntry)> {
+        self.context
+            .render_entries()
+            .map(|(template, entry)| (self.resolve_render_template(template), entry))
+    }
+
+    /// Sets the text rendered in place of variables that have no value, `{name}` in it is
+    /// replaced with the name of the variable, like `<<MISSING:{name}>>`. The placeholder takes
+    /// precedence over rendering the raw tag with `render_raw`. Without a placeholder missing
+    /// variables render as nothing unless `render_raw` is set.
+    pub fn set_missing_placeholder(&mut self, placeholder: Option<String>) {
+        self.missing_placeholder = placeholder;
+    }
+
+    /// Sets the maximum number of files rendered within each other, including the template.
+    pub fn set_max_include_depth(&mut self, depth: usize) {
+        self.max_include_depth = depth;
+    }
+
+    /// Enables or disables the builtin variables like `mold.hostname`, disabling them makes the
+    /// output independent of the machine and time it was rendered on.
+    pub fn set_builtins_enabled(&mut self, enabled: bool) {
+        self.builtins_enabled = enabled;
+    }
+
+    /// Enables or disables rendering the tags in values of variables, which is enabled by
+    /// default. When disabled a variable renders as its value stored in the context, so tags in
+    /// it are emitted literally regardless of `render_raw` and the missing placeholder, and the
+    /// variables they reference are not reported as missing.
+    pub fn set_nested_render(&mut self, enabled: bool) {
+        self.nested_render = enabled;
+    }
+
+    /// Sets the source from which templates and the files included by them are read, by default
+    /// they are read from the filesystem.
+    pub fn set_source(&mut self, source: impl TemplateSource + 'static) {
+        self.source = Box::new(source);
+    }
+
+    /// Allows running the shell commands of `{! !}` tags. Commands are disabled by default as
+    /// templates could run anything, rendering a command tag without allowing them is an error.
+    pub fn allow_commands(&mut self, allow: bool) {
+        self.commands_allowed = allow;
+    }
+
+    /// Sets the time after which a running command is killed and the render fails.
+    pub fn set_command_timeout(&mut self, timeout: Duration) {
+        self.command_timeout = timeout;
+    }
+
+    /// Runs `command` with `sh` and returns its output without trailing newlines.
+    fn run_command(&self, command: &str) -> Result<String> {
+        if !self.commands_allowed {
+            return Err(anyhow!(
+                "refusing to run command `{}`, commands in templates are not allowed",
+                command
+            ));
+        }
+        debug!("running command `{}`", command);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run command `{}`", command))?;
+        // the pipes are read on separate threads so that a command with a lot of output doesn't
+        // block on a full pipe while waiting for it to exit
+        let read = |pipe: Option<Box<dyn io::Read + Send>>| {
+            std::thread::spawn(move || {
+                let mut output = vec![];
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut output);
+                }
+                output
+            })
+        };
+        let stdout = read(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+        let stderr = read(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() >= self.command_timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!(
+                    "command `{}` timed out after {}",
+                    command,
+                    humantime::format_duration(self.command_timeout)
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if !status.success() {
+            return Err(anyhow!(
+                "command `{}` failed - {}: {}",
+                command,
+                status,
+                String::from_utf8_lossy(&stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&stdout)
+            .trim_end_matches(&['\n', '\r'][..])
+            .to_string())
+    }
+
+    /// Returns the value of the builtin variable `name` if builtins are enabled. Builtins are only
+    /// used if the variable is not defined in the context so they can be shadowed.
+    fn builtin(&self, name: &str) -> Option<String> {
+        if !self.builtins_enabled {
+            return None;
+        }
+        match name.strip_prefix(BUILTIN_PREFIX)? {
+            "hostname" => hostname::get()
+                .ok()
+                .map(|hostname| hostname.to_string_lossy().into_owned()),
+            "user" => std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .ok(),
+            "os" => Some(std::env::consts::OS.to_string()),
+            "date" => {
+                let now = humantime::format_rfc3339_seconds(std::time::SystemTime::now());
+                now.to_string().get(..10).map(str::to_string)
+            }
+            "context_file" => self
+                .context_file
+                .as_ref()
+                .map(|file| file.display().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Adds a directory to the end of the include directories.
+    pub fn add_include_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.include_dirs.push(dir.into());
+    }
+
+    pub fn render(&self, input: &str, namespace: Option<&str>, render_raw: bool) -> Result<String> {
+        self.render_scoped(input, RenderScope::new(namespace, render_raw))
+    }
+
+    /// Renders `input` like [`Mold::render`] and reports the variables that were missing.
+    pub fn render_str(
+        &self,
+        input: &str,
+        namespace: Option<&str>,
+        render_raw: bool,
+    ) -> Result<Rendered> {
+        let missing = RefCell::new(vec![]);
+        let output = self.render_scoped(
+            input,
+            RenderScope {
+                missing: Some(&missing),
+                ..RenderScope::new(namespace, render_raw)
+            },
+        )?;
+        Ok(Rendered {
+            output,
+            missing: missing.into_inner(),
+        })
+    }
+
+    /// Renders `input` writing the output directly to `writer` as the tokens are processed
+    /// instead of building the whole output in memory.
+    pub fn render_to<W: Write>(
+        &self,
+        input: &str,
+        namespace: Option<&str>,
+        render_raw: bool,
+        writer: &mut W,
+    ) -> Result<()> {
+        self.render_scoped_to(input, RenderScope::new(namespace, render_raw), writer)
+    }
+
+    fn render_scoped(&self, input: &str, scope: RenderScope<'_>) -> Result<String> {
+        let mut out = Vec::with_capacity(input.len());
+        self.render_scoped_to(input, scope, &mut out)?;
+        String::from_utf8(out).context("rendered output is not valid UTF-8")
+    }
+
+    fn render_scoped_to<W: Write>(
+        &self,
+        input: &str,
+        scope: RenderScope<'_>,
+        writer: &mut W,
+    ) -> Result<()> {
+        let tokens = parser::parse_input(input).context("parsing input error")?;
+        self.check_required(&tokens, scope)?;
+        self.render_tokens(
+            input,
+            tokens,
+            scope,
+            &mut ColumnWriter {
+                inner: writer,
+                column: 0,
+            },
+        )
+    }
+
+    /// Renders the `tokens` parsed from `input` to `writer`.
+    fn render_tokens<W: Write>(
+        &self,
+        input: &str,
+        tokens: Vec<Token<'_>>,
+        scope: RenderScope<'_>,
+        writer: &mut ColumnWriter<'_, W>,
+    ) -> Result<()> {
+        for token in tokens {
+            match token {
+                Token::Text(t) => writer.write_all(t.as_bytes())?,
+                Token::Variable { name, filters, raw } => {
+                    match self
+                        .render_variable(name, &filters, scope, writer.column)
+                        .with_context(|| {
+                            let (line, column) = parser::location(input, raw);
+                            format!(
+                                "failed to render `{}` at line {}, column {}",
+                                raw, line, column
+                            )
+                        })? {
+                        Some(value) => writer.write_all(value.as_bytes())?,
+                        None => match &self.missing_placeholder {
+                            Some(placeholder) => writer.write_all(
+                                placeholder.replace(MISSING_NAME_TAG, name).as_bytes(),
+                            )?,
+                            None if scope.render_raw => writer.write_all(raw.as_bytes())?,
+                            None => {}
+                        },
+                    }
+                }
+                Token::FileSource {
+                    path,
+                    trim,
+                    filters,
+                } => {
+                    let path = self.find_source(path, scope.dir)?;
+                    let error = || format!("failed to read source file `{}`", path.display());
+                    let mut content = self.source.read(&path).map(strip_bom).with_context(error)?;
+                    if trim {
+                        content = content.trim().to_string();
+                    }
+                    if let Some(content) =
+                        self.apply_filters(content, &filters, scope, writer.column)?
+                    {
+                        writer.write_all(content.as_bytes())?;
+                    }
+                }
+                Token::Require(_) => {}
+                Token::Command(command) => {
+                    writer.write_all(self.run_command(command)?.as_bytes())?
+                }
+                Token::IfExists { path, body } => {
+                    let exists = path_exists(path);
+                    debug!("`{}` exists: {}", path, exists);
+                    if exists {
+                        self.render_tokens(input, body, scope, writer)?;
+                    }
+                }
+                Token::If {
+                    name,
+                    negated,
+                    body,
+                    else_body,
+                } => {
+                    let set = self.is_set(name, scope.namespace);
+                    debug!("`{}` is set: {}", name, set);
+                    if set != negated {
+                        self.render_tokens(input, body, scope, writer)?;
+                    } else {
+                        self.render_tokens(input, else_body, scope, writer)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a relative `path` of a file source in the directory of the template, the include
+    /// directories and the current directory in that order. The path is expanded with
+    /// [`expand_path`] first, so `~/file` is absolute.
+    fn find_source(&self, path: &str, dir: Option<&Path>) -> Result<PathBuf> {
+        let path = expand_path(Path::new(path));
+        if path.is_absolute() {
+            return Ok(path);
+        }
+        let mut dirs: Vec<&Path> = vec![];
+        for dir in dir
+            .into_iter()
+            .chain(self.include_dirs.iter().map(PathBuf::as_path))
+            .chain(std::iter::once(
+                self.template_root
+                    .as_deref()
+                    .unwrap_or_else(|| Path::new(".")),
+            ))
+        {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        dirs.iter()
+            .map(|dir| dir.join(&path))
+            .find(|path| self.source.exists(path))
+            .ok_or_else(|| {
+                anyhow!(
+                    "source file `{}` not found, searched in: {}",
+                    path.display(),
+                    dirs.iter()
+                        .map(|dir| format!("`{}`", dir.display()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+
+    /// Verifies that all variables named in `{@require @}` directives are defined, the error lists
+    /// all of the missing ones. Directives in conditional blocks are only checked if the block is
+    /// rendered, included files check their own directives when they are rendered.
+    fn check_required(&self, tokens: &[Token<'_>], scope: RenderScope<'_>) -> Result<()> {
+        let mut missing = vec![];
+        self.collect_required(tokens, scope, &mut missing)?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "missing required variables in namespace `{}`: {}",
+            scope.namespace.unwrap_or(GLOBAL_NS),
+            missing.join(", ")
+        ))
+    }
+
+    /// Adds the variables of `{@require @}` directives in `tokens` and in the rendered branches of
+    /// conditional blocks that are not defined to `missing`.
+    fn collect_required<'t>(
+        &self,
+        tokens: &[Token<'t>],
+        scope: RenderScope<'_>,
+        missing: &mut Vec<&'t str>,
+    ) -> Result<()> {
+        for token in tokens {
+            match token {
+                Token::Require(names) => {
+                    for name in names {
+                        if self.render_variable(name, &[], scope, 0)?.is_none()
+                            && !missing.contains(name)
+                        {
+                            missing.push(*name);
+                        }
+                    }
+                }
+                Token::IfExists { path, body } if path_exists(path) => {
+                    self.collect_required(body, scope, missing)?
+                }
+                Token::If {
+                    name,
+                    negated,
+                    body,
+                    else_body,
+                } => {
+                    let branch = if self.is_set(name, scope.namespace) != *negated {
+                        body
+                    } else {
+                        else_body
+                    };
+                    self.collect_required(branch, scope, missing)?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether the variable `name` is set for a conditional block, that is whether it is
+    /// defined in `namespace`, the global namespace or as a builtin with a value that is not an
+    /// empty string. The raw value is checked, nested variables are not rendered.
+    fn is_set(&self, name: &str, namespace: Option<&str>) -> bool {
+        match self
+            .context
+            .get_variable_value(name, namespace.unwrap_or(GLOBAL_NS))
+        {
+            Some(value) => !value.is_empty(),
+            None => self.builtin(name).is_some_and(|value| !value.is_empty()),
+        }
+    }
+
+    /// Returns the value of the variable `name` resolved the same way as when rendering a
+    /// template or `None` if the variable is not defined.
+    pub fn get_variable(&self, name: &str, namespace: Option<&str>) -> Result<Option<String>> {
+        self.render_variable(name, &[], RenderScope::new(namespace, false), 0)
+    }
+
+    /// Explains how the variable `name` resolves with `namespace`: where its raw value comes
+    /// from, how the variables it references resolve and the final value.
+    pub fn explain_variable(
+        &self,
+        name: &str,
+        namespace: Option<&str>,
+    ) -> Result<VariableExplanation> {
+        self.explain(name, namespace, &mut vec![])
+    }
+
+    /// Explains the variable `name`, `chain` holds the variables whose values reference it.
+    fn explain(
+        &self,
+        name: &str,
+        namespace: Option<&str>,
+        chain: &mut Vec<String>,
+    ) -> Result<VariableExplanation> {
+        let mut explanation = VariableExplanation {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        if chain.iter().any(|referencing| referencing == name) {
+            explanation.cycle = true;
+            return Ok(explanation);
+        }
+        explanation.namespace_value = namespace
+            .filter(|namespace| !self.context.is_global(namespace))
+            .and_then(|namespace| self.context.get_namespace(namespace))
+            .and_then(|namespace| namespace.variables.get(name))
+            .cloned();
+        explanation.global_value = self.context.get_global_variable(name).cloned();
+        match self
+            .context
+            .get_variable_value(name, namespace.unwrap_or(GLOBAL_NS))
+        {
+            Some(raw) => {
+                // values that fail to parse are rendered as is
+                let referenced = referenced_variables(raw).unwrap_or_default();
+                chain.push(name.to_string());
+                for nested in referenced {
+                    explanation
+                        .nested
+                        .push(self.explain(&nested, namespace, chain)?);
+                }
+                chain.pop();
+            }
+            None => {
+                explanation.builtin = self.builtin(name);
+                if explanation.builtin.is_none() {
+                    explanation.similar = self
+                        .context
+                        .similar_variables(name)
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect();
+                }
+            }
+        }
+        if !explanation.has_cycle() {
+            explanation.value = self.get_variable(name, namespace)?;
+        }
+        Ok(explanation)
+    }
+
+    /// Returns the value of the variable `name` with nested variables rendered and all
+    /// `filters` applied or `None` if there is no value to render. The `column` is the column
+    /// of the tag in the rendered output.
+    fn render_variable(
+        &self,
+        name: &str,
+        filters: &[&str],
+        scope: RenderScope<'_>,
+        column: usize,
+    ) -> Result<Option<String>> {
+        // fall back to variables from global namespace
+        let value = scope
+            .namespace
+            .and_then(|ns| {
+                let value = self.context.get_namespace(ns)?.variables.get(name)?;
+                Some((value, ns))
+            })
+            .or_else(|| {
+                self.context
+                    .get_global_variable(name)
+                    .map(|value| (value, GLOBAL_NS))
+            });
+        let value = match value {
+            Some((value, source)) if !self.nested_render => {
+                debug!("variable `{}` resolved from namespace `{}`", name, source);
+                value.clone()
+            }
+            // try to render variable in case it contains nested variables
+            Some((value, source)) => {
+                debug!("variable `{}` resolved from namespace `{}`", name, source);
+                let mut variables = scope.variables.to_vec();
+                variables.push(name);
+                if scope.variables.contains(&name) {
+                    return Err(anyhow!("variable cycle {}", variables.join(" -> ")));
+                }
+                self.render_scoped(
+                    value.as_str(),
+                    RenderScope {
+                        variables: &variables,
+                        ..scope
+                    },
+                )
+                .with_context(|| format!("in the value of variable `{}`", name))?
+            }
+            None => match self.builtin(name) {
+                Some(value) => {
+                    debug!("variable `{}` resolved from builtins", name);
+                    value
+                }
+                None => {
+                    debug!("variable `{}` not found", name);
+                    scope.record_missing(name);
+                    return Ok(None);
+                }
+            },
+        };
+
+        let value = self.apply_filters(value, filters, scope, column)?;
+        if value.is_none() {
+            // the file of the `file` filter doesn't exist
+            scope.record_missing(name);
+        }
+        Ok(value)
+    }
+
+    /// Applies `filters` to `value` in order, returns `None` if there is nothing to render.
+    fn apply_filters(
+        &self,
+        mut value: String,
+        filters: &[&str],
+        scope: RenderScope<'_>,
+        column: usize,
+    ) -> Result<Option<String>> {
+        for filter in filters {
+            let (name, argument) = parser::split_filter(filter);
+            value = match (name, argument) {
+                (FILE_FILTER, None) => {
+                    // a file that doesn't exist is handled like a missing variable
+                    let path = match self.find_source(&value, scope.dir) {
+                        Ok(path) => path,
+                        Err(e) => {
+                            debug!("{}", e);
+                            return Ok(None);
+                        }
+                    };
+                    let content = self
+                        .source
+                        .read(&path)
+                        .map(strip_bom)
+                        .with_context(|| format!("failed to read file `{}`", path.display()))?;
+                    self.render_include(&path, &content, scope)?
+                }
+                (INDENT_FILTER, Some(INDENT_AUTO)) => indent(&value, column),
+                (INDENT_FILTER, Some(width)) => match width.parse() {
+                    Ok(width) => indent(&value, width),
+                    Err(_) => {
+                        return Err(anyhow!("invalid width `{}` of filter `{}`", width, name))
+                    }
+                },
+                (INDENT_FILTER, None) => {
+                    return Err(anyhow!(
+                        "filter `{}` requires a width like `{}:4` or `{}:{}`",
+                        name,
+                        name,
+                        name,
+                        INDENT_AUTO
+                    ))
+                }
+                (FILE_FILTER, Some(_)) => {
+                    return Err(anyhow!("filter `{}` doesn't take an argument", name))
+                }
+                (REPLACE_FILTER, argument) => {
+                    match argument.and_then(parser::filter_arguments).as_deref() {
+                        Some([from, to]) if !from.is_empty() => value.replace(from, to),
+                        _ => {
+                            return Err(anyhow!(
+                                "filter `{}` requires two quoted arguments like `{}(\"/\", \"_\")`, the first one not empty",
+                                name,
+                                name
+                            ))
+                        }
+                    }
+                }
+                _ => return Err(anyhow!("unknown filter `{}`", filter)),
+            };
+        }
+
+        Ok(Some(value))
+    }
+
+    /// Reads the template `file` from the source. Files that aren't UTF-8 text are rejected with
+    /// an error naming them, as rendering would mangle them.
+    fn read_template(&self, file: &Path) -> Result<String> {
+        let not_text = || {
+            anyhow!(
+                "template {} is not valid UTF-8 text (looks binary), use `verbatim: true` if intended",
+                file.display()
+            )
+        };
+        match self.source.read(file) {
+            Ok(input) if is_binary(input.as_bytes()) => Err(not_text()),
+            Ok(input) => Ok(strip_bom(input)),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => Err(not_text()),
+            Err(e) => {
+                Err(e).with_context(|| format!("failed to read template `{}`", file.display()))
+            }
+        }
+    }
+
+    /// Returns the values of the variables marked as secret in the global namespace and in
+    /// `namespace`, resolved with `namespace`. Resolving the values renders them, which may run
+    /// commands, so they are computed once per render and used for every text displayed for it.
+    /// Values shorter than [`MIN_SECRET_LEN`] characters are only redacted as whole words.
+    pub fn secrets(&self, namespace: Option<&str>) -> Secrets {
+        let namespace = namespace.filter(|namespace| !self.context.is_global(namespace));
+        let mut keys: Vec<&str> = vec![];
+        for key in self.context.global.secrets.iter().chain(
+            namespace
+                .and_then(|namespace| self.context.get_namespace(namespace))
+                .into_iter()
+                .flat_map(|namespace| namespace.secrets.iter()),
+        ) {
+            if !keys.contains(&key.as_str()) {
+                keys.push(key);
+            }
+        }
+        let mut values = vec![];
+        for key in keys {
+            match self.get_variable(key, namespace) {
+                Ok(Some(value)) if value.is_empty() => {}
+                Ok(Some(value)) if !values.contains(&value) => values.push(value),
+                _ => {}
+            }
+        }
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        Secrets { values }
+    }
+
+    /// Renders the template `file`, relative paths of file sources are looked up in the directory
+    /// of the template first. Errors name the template, or the chain of includes if they occurred
+    /// in an included file.
+    pub fn render_file(
+        &self,
+        file: &std::path::Path,
+        namespace: Option<&str>,
+        render_raw: bool,
+    ) -> Result<String> {
+        let input = self.read_template(file)?;
+        self.render_include(file, &input, RenderScope::new(namespace, render_raw))
+    }
+
+    /// Renders every template in `renders` without writing anything and returns the rendered
+    /// content keyed by the output path. Entries with their own namespace are rendered with it,
+    /// the others with `namespace`. `~` in the paths is expanded and templates are resolved with
+    /// [`Mold::resolve_render_template`]. Verbatim entries are skipped as they aren't rendered.
+    pub fn render_context(
+        &self,
+        namespace: Option<&str>,
+        render_raw: bool,
+    ) -> Result<HashMap<PathBuf, String>> {
+        let mut outputs = HashMap::new();
+        for (template, entry) in self
+            .context
+            .render_entries()
+            .filter(|(_, entry)| !entry.verbatim())
+        {
+            let template = self.resolve_render_template(&expand_path(template));
+            let content =
+                self.render_file(&template, entry.namespace().or(namespace), render_raw)?;
+            let output = expand_path(entry.output());
+            if outputs.insert(output.clone(), content).is_some() {
+                return Err(anyhow!(
+                    "output `{}` is rendered by more than one template",
+                    output.display()
+                ));
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Renders the template `file` like [`Mold::render_file`] and reports the variables that were
+    /// missing.
+    pub fn render_file_with_missing(
+        &self,
+        file: &Path,
+        namespace: Option<&str>,
+        render_raw: bool,
+    ) -> Result<Rendered> {
+        let input = self.read_template(file)?;
+        let missing = RefCell::new(vec![]);
+        let output = self.render_include(
+            file,
+            &input,
+            RenderScope {
+                missing: Some(&missing),
+                ..RenderScope::new(namespace, render_raw)
+            },
+        )?;
+        Ok(Rendered {
+            output,
+            missing: missing.into_inner(),
+        })
+    }
+
+    /// Renders `input` read from `file` with the file added to the chain of includes. Fails if
+    /// the file is already being rendered or the chain is longer than the maximum include depth.
+    fn render_include(&self, file: &Path, input: &str, scope: RenderScope<'_>) -> Result<String> {
+        let include = Include {
+            path: file.to_path_buf(),
+            canonical: self.source.canonicalize(file),
+        };
+        let cycle = scope
+            .includes
+            .iter()
+            .any(|included| included.canonical == include.canonical);
+        let mut includes = scope.includes.to_vec();
+        includes.push(include);
+        if cycle {
+            return Err(anyhow!(
+                "include cycle detected: {}",
+                display_includes(&includes)
+            ));
+        }
+        if includes.len() > self.max_include_depth {
+            return Err(anyhow!(
+                "maximum include depth of {} exceeded: {}",
+                self.max_include_depth,
+                display_includes(&includes)
+            ));
+        }
+        let rendered = self.render_scoped(
+            input,
+            RenderScope {
+                dir: file.parent(),
+                includes: &includes,
+                ..scope
+            },
+        );
+        // the whole chain of includes names the included file together with the template
+        rendered.with_context(|| match includes.as_slice() {
+            [template] => format!("failed to render `{}`", template.path.display()),
+            includes => format!("failed to render {}", display_includes(includes)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTEXT: &str = r#"
+namespaces:
+  - name: GLOBAL
+    variables:
+      name: mold
+      partial: partial.txt
+      missing_partial: missing.txt
+      path: /usr/local/bin
+      lines: "a\nb\n\nc"
+"#;
+
+    /// Returns a mold with the context parsed from `yaml` that reads templates and file sources
+    /// from `files` held in memory.
+    fn mold(yaml: &str, files: &[(&str, &str)]) -> Mold {
+        let ctx: SerializedContext = serde_yaml::from_str(yaml).unwrap();
+        let mut source = MemorySource::new();
+        for (path, content) in files {
+            source.insert(*path, *content);
+        }
+        let mut context: Context = ctx.try_into().unwrap();
+        context.finish().unwrap();
+        let mut mold = Mold {
+            context,
+            ..Default::default()
+        };
+        mold.set_source(source);
+        mold
+    }
+
+    fn render_err(mold: &Mold, input: &str) -> String {
+        format!("{:?}", mold.render(input, None, false).unwrap_err())
+    }
+
+    #[test]
+    fn file_filter_inlines_rendered_file() {
+        let mold = mold(CONTEXT, &[("partial.txt", "hello {% name %}")]);
+        assert_eq!(
+            mold.render("<{% partial | file %}>", None, false).unwrap(),
+            "<hello mold>"
+        );
+    }
+
+    #[test]
+    fn file_filter_of_missing_file_follows_render_mode() {
+        let mold = mold(CONTEXT, &[]);
+        let input = "<{% missing_partial | file %}>";
+        assert_eq!(mold.render(input, None, false).unwrap(), "<>");
+        assert_eq!(mold.render(input, None, true).unwrap(), input);
+    }
+
+    #[test]
+    fn file_filter_of_missing_file_is_reported_as_missing() {
+        let mold = mold(CONTEXT, &[]);
+        let rendered = mold
+            .render_str("<{% missing_partial | file %}>", None, false)
+            .unwrap();
+        assert_eq!(rendered.output, "<>");
+        assert_eq!(rendered.missing, ["missing_partial"]);
+    }
+
+    #[test]
+    fn file_filter_expands_home() {
+        let home = dirs::home_dir().unwrap().join("partial.txt");
+        let yaml = "namespaces:\n  - name: GLOBAL\n    variables:\n      partial: ~/partial.txt\n";
+        let mold = mold(yaml, &[(home.to_str().unwrap(), "at home")]);
+        assert_eq!(
+            mold.render("{% partial | file %}", None, false).unwrap(),
+            "at home"
+        );
+    }
+
+    #[test]
+    fn file_source_expands_home_and_variables() {
+        let home = dirs::home_dir().unwrap().join("partial.txt");
+        let mold = mold(CONTEXT, &[(home.to_str().unwrap(), "at home")]);
+        for template in ["{@ ~/partial.txt @}", "{@ $HOME/partial.txt @}"] {
+            assert_eq!(mold.render(template, None, false).unwrap(), "at home");
+        }
+    }
+
+    #[test]
+    fn file_filter_looks_up_file_like_file_sources() {
+        let mold = mold(
+            CONTEXT,
+            &[
+                ("templates/main.txt", "{% partial | file %}"),
+                ("templates/partial.txt", "next to the template"),
+                ("partial.txt", "in the current directory"),
+            ],
+        );
+        assert_eq!(
+            mold.render_file(Path::new("templates/main.txt"), None, false)
+                .unwrap(),
+            "next to the template"
+        );
+        let mut mold = self::mold(CONTEXT, &[("partials/partial.txt", "in an include dir")]);
+        mold.add_include_dir("partials");
+        assert_eq!(
+            mold.render("{% partial | file %}", None, false).unwrap(),
+            "in an include dir"
+        );
+    }
+
+    /// Source whose files all exist but can't be read.
+    #[derive(Debug)]
+    struct UnreadableSource;
+
+    impl TemplateSource for UnreadableSource {
+        fn read(&self, _: &Path) -> io::Result<String> {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied"))
+        }
+
+        fn exists(&self, _: &Path) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn file_filter_of_unreadable_file_is_an_error() {
+        let mut mold = mold(CONTEXT, &[]);
+        mold.set_source(UnreadableSource);
+        let error = render_err(&mold, "{% partial | file %}");
+        assert!(
+            error.contains("failed to read file `./partial.txt`"),
+            "{}",
+            error
+        );
+        assert!(error.contains("denied"), "{}", error);
+    }
+
+    #[test]
+    fn file_filter_rejects_argument() {
+        let mold = mold(CONTEXT, &[("partial.txt", "")]);
+        assert!(render_err(&mold, "{% partial | file:x %}")
+            .contains("filter `file` doesn't take an argument"));
+    }
+
+    #[test]
+    fn indent_filter_indents_lines_after_the_first() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render("x:\n  {% lines | indent:2 %}", None, false)
+                .unwrap(),
+            "x:\n  a\n  b\n\n  c"
+        );
+    }
+
+    #[test]
+    fn indent_auto_indents_to_column_of_tag() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render("key: {% lines | indent:auto %}", None, false)
+                .unwrap(),
+            "key: a\n     b\n\n     c"
+        );
+    }
+
+    #[test]
+    fn indent_filter_requires_valid_width() {
+        let mold = mold(CONTEXT, &[]);
+        assert!(render_err(&mold, "{% lines | indent %}").contains("requires a width"));
+        assert!(render_err(&mold, "{% lines | indent:wide %}")
+            .contains("invalid width `wide` of filter `indent`"));
+    }
+
+    #[test]
+    fn replace_filter_replaces_all_occurrences() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render(r#"{% path | replace("/", "_") %}"#, None, false)
+                .unwrap(),
+            "_usr_local_bin"
+        );
+    }
+
+    #[test]
+    fn replace_filter_requires_two_arguments() {
+        let mold = mold(CONTEXT, &[]);
+        for input in [
+            r#"{% path | replace("/") %}"#,
+            r#"{% path | replace("", "_") %}"#,
+            "{% path | replace:x %}",
+        ] {
+            assert!(
+                render_err(&mold, input).contains("requires two quoted arguments"),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn filters_are_applied_in_order() {
+        let mold = mold(CONTEXT, &[("partial.txt", "a/b\nc/d")]);
+        assert_eq!(
+            mold.render(
+                r#"  {% partial | file | indent:auto | replace("/", ".") %}"#,
+                None,
+                false
+            )
+            .unwrap(),
+            "  a.b\n  c.d"
+        );
+    }
+
+    #[test]
+    fn file_source_is_included_as_is() {
+        let mold = mold(CONTEXT, &[("newline.txt", "x\n"), ("no_newline.txt", "x")]);
+        assert_eq!(
+            mold.render("a {@ newline.txt @} b", None, false).unwrap(),
+            "a x\n b"
+        );
+        assert_eq!(
+            mold.render("a {@ no_newline.txt @} b", None, false)
+                .unwrap(),
+            "a x b"
+        );
+    }
+
+    #[test]
+    fn trim_tags_remove_whitespace_of_the_file_only() {
+        let mold = mold(
+            CONTEXT,
+            &[
+                ("newline.txt", "x\n"),
+                ("no_newline.txt", "x"),
+                ("padded.txt", "\r\n \t x y \n\n"),
+            ],
+        );
+        for file in ["newline.txt", "no_newline.txt"] {
+            assert_eq!(
+                mold.render(&format!("a {{@~ {} ~@}} b\n", file), None, false)
+                    .unwrap(),
+                "a x b\n"
+            );
+        }
+        assert_eq!(
+            mold.render("{@~ padded.txt ~@}\nnext", None, false)
+                .unwrap(),
+            "x y\nnext"
+        );
+    }
+
+    #[test]
+    fn indent_filter_nests_included_yaml() {
+        let mold = mold(
+            CONTEXT,
+            &[("settings.yaml", "\nfont: mono\n\nsize: 12\n\n")],
+        );
+        assert_eq!(
+            mold.render(
+                "settings:\n  {@~ settings.yaml | indent:auto ~@}\nother: 1\n",
+                None,
+                false
+            )
+            .unwrap(),
+            "settings:\n  font: mono\n\n  size: 12\nother: 1\n"
+        );
+    }
+
+    #[test]
+    fn if_exists_renders_block_only_if_path_exists() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render(
+                "{% if_exists / %}a {% name %}{% endif_exists %}{% if_exists /does/not/exist %}b{% endif_exists %}",
+                None,
+                false
+            )
+            .unwrap(),
+            "a mold"
+        );
+    }
+
+    #[test]
+    fn if_exists_expands_environment_variables() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render(
+                "{% if_exists $CARGO_MANIFEST_DIR/Cargo.toml %}a{% endif_exists %}{% if_exists ${CARGO_MANIFEST_DIR}/missing %}b{% endif_exists %}",
+                None,
+                false
+            )
+            .unwrap(),
+            "a"
+        );
+    }
+
+    #[test]
+    fn nested_if_exists_blocks_are_rendered() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render(
+                "{% if_exists / %}a{% if_exists /does/not/exist %}b{% endif_exists %}c{% endif_exists %}",
+                None,
+                false
+            )
+            .unwrap(),
+            "ac"
+        );
+    }
+
+    #[test]
+    fn raw_block_inside_if_exists_is_text() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render(
+                "{% if_exists / %}{% raw %}{% name %}{% endif_exists %}{% endraw %}{% endif_exists %}",
+                None,
+                false
+            )
+            .unwrap(),
+            "{% name %}{% endif_exists %}"
+        );
+    }
+
+    #[test]
+    fn require_lists_all_missing_variables() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render("{@require name @}\n{% name %}", None, false)
+                .unwrap(),
+            "mold"
+        );
+        assert!(render_err(&mold, "{@require a, name, b @}")
+            .contains("missing required variables in namespace `GLOBAL`: a, b"));
+    }
+
+    #[test]
+    fn require_in_rendered_branches_is_checked() {
+        let mold = mold(CONTEXT, &[]);
+        assert!(
+            render_err(&mold, "{% if name %}{@require a @}\n{% endif %}")
+                .contains("missing required variables in namespace `GLOBAL`: a")
+        );
+        assert!(
+            render_err(&mold, "{% if !name %}{% else %}{@require b @}\n{% endif %}")
+                .contains("missing required variables in namespace `GLOBAL`: b")
+        );
+        assert!(
+            render_err(&mold, "{% if_exists / %}{@require c @}\n{% endif_exists %}")
+                .contains("missing required variables in namespace `GLOBAL`: c")
+        );
+    }
+
+    #[test]
+    fn require_in_skipped_branches_is_ignored() {
+        let mold = mold(CONTEXT, &[]);
+        assert_eq!(
+            mold.render(
+                "{% if unset %}{@require a @}\n{% endif %}{% if_exists /does/not/exist %}{@require b @}\n{% endif_exists %}",
+                None,
+                false
+            )
+            .unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn require_in_included_file_is_checked() {
+        let mold = mold(CONTEXT, &[("partial.txt", "{@require a @}\n")]);
+        assert!(render_err(&mold, "{% partial | file %}")
+            .contains("missing required variables in namespace `GLOBAL`: a"));
+    }
+
+    #[test]
+    fn variable_referencing_itself_is_an_error() {
+        let yaml = r#"
+namespaces:
+  - name: GLOBAL
+    variables:
+      loop: "{% loop %}"
+      a: "x {% b %}"
+      b: "{% c %}"
+      c: "{% a %}"
+"#;
+        let mold = mold(yaml, &[]);
+        let err = render_err(&mold, "{% loop %}");
+        assert!(err.contains("variable cycle loop -> loop"), "{}", err);
+        let err = render_err(&mold, "first\n  {% a %}");
+        assert!(err.contains("variable cycle a -> b -> c -> a"), "{}", err);
+        assert!(
+            err.contains("failed to render `{% a %}` at line 2, column 3"),
+            "{}",
+            err
+        );
+        let err = format!("{:?}", mold.get_variable("b", None).unwrap_err());
+        assert!(err.contains("variable cycle b -> c -> a -> b"), "{}", err);
+    }
+
+    #[test]
+    fn errors_in_values_are_not_ignored() {
+        let yaml = r#"
+namespaces:
+  - name: GLOBAL
+    variables:
+      command: "{! echo hi !}"
+      invalid: "x {% %} y"
+      filter: "{% name | upper %}"
+      require: "{@require unset @}"
+      name: mold
+"#;
+        let mold = mold(yaml, &[]);
+        for (name, error) in [
+            ("command", "commands in templates are not allowed"),
+            ("invalid", "empty variable name"),
+            ("filter", "unknown filter `upper`"),
+            ("require", "missing required variables"),
+        ] {
+            let err = render_err(&mold, &format!("{{% {} %}}", name));
+            assert!(
+                err.contains(&format!("in the value of variable `{}`", name)),
+                "{}",
+                err
+            );
+            assert!(err.contains(error), "{}", err);
+        }
+    }
+
+    #[test]
+    fn variable_referenced_twice_is_not_a_cycle() {
+        let yaml = r#"
+namespaces:
+  - name: GLOBAL
+    variables:
+      name: mold
+      both: "{% name %} {% name %}"
+"#;
+        let mold = mold(yaml, &[]);
+        assert_eq!(
+            mold.render("{% both %} {% name %}", None, false).unwrap(),
+            "mold mold mold"
+        );
+    }
+
+    #[test]
+    fn unknown_filter_is_an_error() {
+        let mold = mold(CONTEXT, &[]);
+        assert!(render_err(&mold, "{% name | upper %}").contains("unknown filter `upper`"));
+    }
+
+    #[test]
+    fn raw_blocks_escape_both_tags() {
+        let syntax = "Variables look like {% raw %}{% name %}{% endraw %} and file sources \
+                      like {% raw %}{@ path @}{% endraw %}, {@ and {% are left alone.\n";
+        let mold = mold(CONTEXT, &[("syntax.md", syntax)]);
+        let expected = "Variables look like {% name %} and file sources like {@ path @}, \
+                        {@ and {% are left alone.\n";
+        assert_eq!(mold.render(syntax, None, false).unwrap(), expected);
+        // file sources are included without rendering them
+        assert_eq!(mold.render("{@ syntax.md @}", None, false).unwrap(), syntax);
+    }
+
+    const CONDITIONS: &str = r#"
+namespaces:
+  - name: GLOBAL
+    variables:
+      set: value
+      empty: ""
+      reference: "{% unset %}"
+"#;
+
+    #[test]
+    fn if_checks_whether_variable_is_set() {
+        let mold = mold(CONDITIONS, &[]);
+        let render = |input: &str| mold.render(input, None, false).unwrap();
+        for (name, is_set) in [
+            ("set", true),
+            ("empty", false),
+            ("unset", false),
+            ("reference", true),
+        ] {
+            let expected = if is_set { "yes" } else { "" };
+            assert_eq!(
+                render(&format!("{{% if {} %}}yes{{% endif %}}", name)),
+                expected
+            );
+            let expected = if is_set { "" } else { "no" };
+            assert_eq!(
+                render(&format!("{{% if !{} %}}no{{% endif %}}", name)),
+                expected
+            );
+            let expected = if is_set { "yes" } else { "no" };
+            assert_eq!(
+                render(&format!(
+                    "{{% if {} %}}yes{{% else %}}no{{% endif %}}",
+                    name
+                )),
+                expected
+            );
+            let expected = if is_set { "no" } else { "yes" };
+            assert_eq!(
+                render(&format!(
+                    "{{% if !{} %}}yes{{% else %}}no{{% endif %}}",
+                    name
+                )),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn nested_if_blocks() {
+        let mold = mold(CONDITIONS, &[]);
+        let input = "{% if set %}a{% if !empty %}b{% else %}c{% endif %}{% else %}d{% endif %}";
+        assert_eq!(mold.render(input, None, false).unwrap(), "ab");
+        let input = "{% if !set %}a{% else %}{% if unset %}b{% else %}c{% endif %}{% endif %}";
+        assert_eq!(mold.render(input, None, false).unwrap(), "c");
+    }
+
+    #[test]
+    fn checked_variables_are_not_missing() {
+        let mold = mold(CONDITIONS, &[]);
+        let rendered = mold
+            .render_str("{% if !unset %}fallback{% endif %}", None, false)
+            .unwrap();
+        assert_eq!(rendered.output, "fallback");
+        assert!(rendered.missing.is_empty());
+    }
+
+    const SECRETS: &str = r#"
+namespaces:
+  - name: GLOBAL
+    secrets: [token]
+    variables:
+      token: "{% prefix %}-123456"
+      prefix: ghp
+      pin: "1234"
+  - name: work
+    secrets: [password, pin]
+    variables:
+      password: hunter22
+  - name: home
+    variables:
+      password: hunter22
+"#;
+
+    #[test]
+    fn secrets_of_the_namespace_are_redacted() {
+        let mold = mold(SECRETS, &[]);
+        let text = "token=ghp-123456 prefix=ghp password=hunter22 pin=1234";
+        assert_eq!(
+            mold.secrets(Some("work")).redact(text),
+            "token=**** prefix=ghp password=**** pin=****"
+        );
+        assert_eq!(
+            mold.secrets(Some("home")).redact(text),
+            "token=**** prefix=ghp password=hunter22 pin=1234"
+        );
+        assert_eq!(
+            mold.secrets(None).redact(text),
+            "token=**** prefix=ghp password=hunter22 pin=1234"
+        );
+    }
+
+    #[test]
+    fn short_secrets_are_redacted_as_whole_words() {
+        let mold = mold(SECRETS, &[]);
+        assert_eq!(
+            mold.secrets(Some("work"))
+                .redact("1234\nport 12345, pin_1234 x1234 11234 (1234) é1234 1234é 1234"),
+            "****\nport 12345, pin_1234 x1234 11234 (****) é1234 1234é ****"
+        );
+    }
+
+    #[test]
+    fn template_errors_name_the_template() {
+        let mold = mold(CONTEXT, &[("templates/invalid.mold", "a\n{% %}")]);
+        let err = format!(
+            "{:?}",
+            mold.render_file(Path::new("templates/missing.mold"), None, false)
+                .unwrap_err()
+        );
+        assert!(err.contains("failed to read template `templates/missing.mold`"));
+        let err = format!(
+            "{:?}",
+            mold.render_file(Path::new("templates/invalid.mold"), None, false)
+                .unwrap_err()
+        );
+        assert!(err.contains("templates/invalid.mold"));
+        assert!(err.contains("empty variable name at line 2, column 1"));
+    }
+
+    #[test]
+    fn context_file_errors_name_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.yaml");
+        let err = format!("{:?}", Mold::new(&missing).unwrap_err());
+        assert!(err.contains(&format!(
+            "failed to read context file `{}`",
+            missing.display()
+        )));
+        let invalid = dir.path().join("invalid.yaml");
+        std::fs::write(&invalid, "namespaces:\n  - name: [dark\n").unwrap();
+        let err = format!("{:?}", Mold::new(&invalid).unwrap_err());
+        assert!(err.contains(&format!(
+            "failed to parse context file `{}`",
+            invalid.display()
+        )));
+        assert!(err.contains("at line 3 column 1"));
+    }
+
+    #[test]
+    fn byte_order_mark_is_dropped() {
+        let mold = mold(
+            CONTEXT,
+            &[
+                ("bom.mold", "\u{feff}hello {% name %}\n"),
+                ("partial.txt", "\u{feff}partial"),
+            ],
+        );
+        assert_eq!(
+            mold.render_file(Path::new("bom.mold"), None, false)
+                .unwrap(),
+            "hello mold\n"
+        );
+        assert_eq!(
+            mold.render("[{@ partial.txt @}] [{% partial | file %}]", None, false)
+                .unwrap(),
+            "[partial] [partial]"
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings_are_kept() {
+        let mold = mold(
+            CONTEXT,
+            &[
+                (
+                    "crlf.mold",
+                    "\u{feff}{@meta namespace = \"GLOBAL\" @}\r\nhello\r\n",
+                ),
+                ("partial.txt", "\r\n x\r\n\r\n"),
+            ],
+        );
+        let input = "{@require name @}\r\n{% if name %}a {% name %}{% endif %}\r\n\
+                     {@~ partial.txt ~@}\r\n";
+        assert_eq!(mold.render(input, None, false).unwrap(), "a mold\r\nx\r\n");
+        assert_eq!(
+            mold.render_file(Path::new("crlf.mold"), None, false)
+                .unwrap(),
+            "hello\r\n"
+        );
+    }
+
+    #[test]
+    fn env_file_with_byte_order_mark_and_crlf() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.env");
+        std::fs::write(&path, "\u{feff}EMAIL=me@work.example\r\nNAME=me\r\n").unwrap();
+        let namespace = Namespace::from_env_file(&path).unwrap();
+        assert_eq!(namespace.get("EMAIL").unwrap(), "me@work.example");
+        assert_eq!(namespace.get("NAME").unwrap(), "me");
+    }
+
+    #[test]
+    fn relative_templates_are_resolved_against_context_dir() {
+        // the context lives in a temporary directory which is never the current directory of
+        // the tests, so the templates are only found relative to the context file
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        assert_ne!(std::env::current_dir().unwrap(), dir);
+        std::fs::create_dir(dir.join("templates")).unwrap();
+        std::fs::write(dir.join("templates/foo.mold"), "foo {% name %}").unwrap();
+        let absolute = dir.join("absolute.mold");
+        std::fs::write(&absolute, "absolute").unwrap();
+        let context_file = dir.join("context.yaml");
+        std::fs::write(
+            &context_file,
+            format!(
+                "renders:\n  templates/foo.mold: foo.conf\n  {}: absolute.conf\n\
+                 namespaces:\n  - name: GLOBAL\n    variables:\n      name: mold\n",
+                absolute.display()
+            ),
+        )
+        .unwrap();
+        let mut mold = Mold::new(&context_file).unwrap();
+        assert_eq!(
+            mold.resolve_render_template(Path::new("templates/foo.mold")),
+            dir.join("templates/foo.mold")
+        );
+        assert_eq!(mold.resolve_render_template(&absolute), absolute);
+        let outputs = mold.render_context(None, false).unwrap();
+        assert_eq!(outputs[Path::new("foo.conf")], "foo mold");
+        assert_eq!(outputs[Path::new("absolute.conf")], "absolute");
+
+        mold.set_template_root("/root");
+        assert_eq!(
+            mold.resolve_render_template(Path::new("templates/foo.mold")),
+            Path::new("/root/templates/foo.mold")
+        );
+        assert_eq!(mold.resolve_render_template(&absolute), absolute);
+    }
+
+    #[test]
+    fn expand_windows_paths() {
+        let home = || Some(PathBuf::from(r"C:\Users\me"));
+        let context = |var: &str| match var {
+            "APPDATA" => Some(r"C:\Users\me\AppData\Roaming".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let expand = |path| expand_windows(path, home, context);
+        assert_eq!(expand(r"~\.config\mold"), r"C:\Users\me\.config\mold");
+        assert_eq!(
+            expand(r"%APPDATA%\mold\context.yaml"),
+            r"C:\Users\me\AppData\Roaming\mold\context.yaml"
+        );
+        assert_eq!(expand(r"a%EMPTY%b"), "ab");
+        // unset variables, a lone `%` and `~` in the middle of the path are kept
+        assert_eq!(expand(r"%UNSET%\100%\%%"), r"%UNSET%\100%\%%");
+        assert_eq!(expand(r"C:\~\x"), r"C:\~\x");
+        assert_eq!(expand_windows(r"~\x", || None, context), r"~\x");
+    }
+
+    fn context(yaml: &str) -> Context {
+        let ctx: SerializedContext = serde_yaml::from_str(yaml).unwrap();
+        ctx.try_into().unwrap()
+    }
+
+    fn context_err(yaml: &str) -> String {
+        let ctx: SerializedContext = serde_yaml::from_str(yaml).unwrap();
+        Context::try_from(ctx).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn duplicate_namespaces_are_an_error() {
+        let yaml = r#"
+namespaces:
+  - name: nord
+    variables: {}
+  - name: dark
+    variables: {}
+  - name: nord
+    variables: {}
+  - name: dark
+    variables: {}
+  - name: nord
+    variables: {}
+"#;
+        assert_eq!(
+            context_err(yaml),
+            "namespaces defined more than once: nord, dark"
+        );
+    }
+
+    #[test]
+    fn global_namespace_in_the_list_replaces_global_section() {
+        let yaml = r#"
+global:
+  name: GLOBAL
+  variables:
+    font: sans
+    size: "11"
+namespaces:
+  - name: GLOBAL
+    variables:
+      font: mono
+  - name: dark
+    variables: {}
+"#;
+        let context = context(yaml);
+        assert_eq!(context.namespace_names(), ["dark"]);
+        assert_eq!(context.get_variable_value("font", "dark").unwrap(), "mono");
+        assert_eq!(context.get_variable_value("size", GLOBAL_NS), None);
+        let yaml = r#"
+namespaces:
+  - name: GLOBAL
+    variables: {}
+  - name: GLOBAL
+    variables: {}
+"#;
+        assert_eq!(
+            context_err(yaml),
+            "namespaces defined more than once: GLOBAL"
+        );
+    }
+
+    const BASE: &str = r#"
+defaults:
+  accent: blue
+  font: mono
+renders:
+  alacritty.mold:
+    output: alacritty.yml
+    namespace: host
+namespaces:
+  - name: dark
+    variables:
+      font: sans
+"#;
+
+    const HOST: &str = r#"
+defaults:
+  accent: cyan
+default_namespace: host
+namespaces:
+  - name: host
+    variables: {}
+"#;
+
+    #[test]
+    fn render_entry_can_use_global_namespace() {
+        let yaml = r#"
+default_namespace: GLOBAL
+renders:
+  alacritty.mold:
+    output: alacritty.yml
+    namespace: GLOBAL
+namespaces: []
+"#;
+        let mut context = context(yaml);
+        context.finish().unwrap();
+    }
+
+    #[test]
+    fn render_entry_namespace_can_ignore_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let context_file = dir.path().join("context.yaml");
+        std::fs::write(
+            &context_file,
+            "default_namespace: DARK\n\
+             renders:\n  alacritty.mold:\n    output: alacritty.yml\n    namespace: Dark\n\
+             namespaces:\n  - name: dark\n    variables: {}\n",
+        )
+        .unwrap();
+        let files = [context_file];
+        Mold::new_merged(&files, false, true).unwrap();
+        let err = Mold::new_merged(&files, false, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "render entry `alacritty.mold` uses namespace `Dark` which is not defined"
+        );
+    }
+
+    #[test]
+    fn defaults_are_added_after_merging() {
+        let mut merged = context(BASE);
+        merged.merge(context(HOST));
+        merged.finish().unwrap();
+        let value = |key, namespace| merged.get_variable_value(key, namespace).unwrap();
+        assert_eq!(value("accent", "host"), "cyan");
+        assert_eq!(value("font", "host"), "mono");
+        assert_eq!(value("accent", "dark"), "cyan");
+        assert_eq!(value("font", "dark"), "sans");
+    }
+
+    #[test]
+    fn namespaces_are_checked_after_merging() {
+        let err = context(BASE).finish().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "render entry `alacritty.mold` uses namespace `host` which is not defined"
+        );
+        let mut merged = context(BASE);
+        merged.merge(context(HOST));
+        merged.finish().unwrap();
+        let err = context(HOST.replace("name: host", "name: other").as_str())
+            .finish()
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "default namespace `host` is not defined");
     }
 }
@@ -1,9 +1,34 @@
+mod adopt;
+mod emit;
+mod export;
+mod header;
+mod import;
+mod init;
+mod manifest;
+mod palette;
+mod prerender;
+mod side_by_side;
+mod state;
+
+use anyhow::Context as ErrorContext;
 use clap::Parser;
 use colored::{Color, Colorize};
-use mold::Mold;
+use export::ExportFormat;
+use indexmap::IndexMap;
+use log::{debug, info, warn};
+use manifest::{Drift, Manifest};
+use mold::{
+    expand_path, FinalNewline, Mold, Newline, RenderEntry, RenderMode, Rendered, TemplateMeta,
+    TemplateToken, TokenKind, VariableExplanation,
+};
+use palette::PaletteFormat;
+use prerender::Prerendered;
+use serde::Serialize;
 use similar::ChangeTag;
-use std::io;
-use std::path::{Path, PathBuf};
+use state::StateFile;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
 
 macro_rules! exit {
     ($($t:tt)+) => {{
@@ -27,48 +52,368 @@ macro_rules! exit {
 struct Opts {
     #[clap(subcommand)]
     command: Subcommand,
+    #[clap(long, global = true)]
+    /// Location of the state file. Defaults to `$MOLD_STATE_FILE` or
+    /// `$XDG_STATE_HOME/mold/state.yaml`.
+    state_file: Option<PathBuf>,
+    #[clap(short, long, parse(from_occurrences), global = true)]
+    /// Also print unchanged and skipped files and how long rendering took, pass twice for debug
+    /// logs like where every variable was resolved from and three times for trace logs. Logging
+    /// can also be configured with `MOLD_LOG` or `RUST_LOG`.
+    verbose: usize,
+    #[clap(short, long, global = true)]
+    /// Suppresses informational output like the saved files and warnings, errors and diffs
+    /// are still printed.
+    quiet: bool,
+    #[clap(long = "set", global = true, parse(try_from_str = parse_variable_override))]
+    /// Sets a variable overriding the value from the context file, can be specified multiple
+    /// times. Use `key=value` to override the variable in all namespaces or
+    /// `namespace.key=value` to set it only in an existing namespace.
+    overrides: Vec<(String, String)>,
+    #[clap(long = "include-dir", global = true)]
+    /// Directory in which relative paths of file sources are looked up, can be specified
+    /// multiple times. The directory of the template and the `include_dirs` of the context file
+    /// are searched first.
+    include_dirs: Vec<PathBuf>,
+    #[clap(long, global = true)]
+    /// Disables the builtin `mold.*` variables like `mold.hostname`.
+    no_builtins: bool,
+    #[clap(long, global = true)]
+    /// Render variables as their values stored in the context without rendering the tags in
+    /// them, so values can contain mold syntax that is output literally.
+    no_nested_render: bool,
+    #[clap(long, global = true)]
+    /// Allows templates to run shell commands in `{! !}` tags and insert their output.
+    allow_commands: bool,
+    #[clap(long, global = true)]
+    /// Match namespace names case-insensitively if there is no namespace with the exact name.
+    ignore_namespace_case: bool,
+    #[clap(long, global = true)]
+    /// Directory against which relative template paths and relative paths of file sources are
+    /// resolved instead of the current directory, or the directory of the context file for the
+    /// templates in `renders`. Absolute and `~` paths are not affected.
+    template_root: Option<PathBuf>,
+    #[clap(long, global = true)]
+    /// Text rendered in place of variables that have no value, `{name}` is replaced with the
+    /// name of the variable, like `<<MISSING:{name}>>`. Takes precedence over `--show-missing`.
+    missing_placeholder: Option<String>,
+    #[clap(long, global = true)]
+    /// Don't print a warning for every variable without a value when rendering.
+    no_warn_missing: bool,
+    #[clap(long, global = true)]
+    /// Fail on context files declaring a `version` of the format that this version of mold
+    /// doesn't support, by default only a warning is printed.
+    strict: bool,
+    #[clap(long, global = true)]
+    /// Write all outputs with elevated privileges using `--elevate-command`, templates are
+    /// still rendered as the current user. Entries with `privileged: true` are always written
+    /// this way.
+    sudo: bool,
+    #[clap(long, global = true, default_value = "sudo")]
+    /// Command used to write privileged outputs, like `sudo`, `doas` or `run0`.
+    elevate_command: String,
 }
 
 #[derive(Debug, Parser)]
 enum Subcommand {
     /// Renders specified files with a given context.
     Render {
-        /// Input files to render.
+        /// Input files to render. Use `-` to read the template from standard input.
         templates: Vec<PathBuf>,
-        #[clap(short, long)]
-        /// Location of the context file to use for rendering.
-        context_file: PathBuf,
+        #[clap(flatten)]
+        context: ContextFiles,
         #[clap(short, long)]
         /// If specified the rendered content will be placed to this location, otherwise it will be
-        /// printed to standard output.
-        output_path: Option<PathBuf>,
+        /// printed to standard output. Use `-` to print only the rendered content to standard
+        /// output, headers and separators are then printed to standard error. If this is a
+        /// directory the rendered files are placed in it with the name of the template adjusted
+        /// by `--strip-suffix` and `--output-extension`. Can be specified multiple times together
+        /// with the same number of `--namespace` options to render each template once per pair.
+        output_path: Vec<PathBuf>,
+        #[clap(long)]
+        /// Suffix removed from the template file name when rendering to a directory,
+        /// for example `.tmpl`.
+        strip_suffix: Option<String>,
+        #[clap(long)]
+        /// Extension added to, or replacing the extension of, the template file name when
+        /// rendering to a directory.
+        output_extension: Option<String>,
+        #[clap(long, parse(try_from_str = mold::parse_permissions))]
+        /// Permissions in octal notation, like `0600`, set on the saved files. If not specified
+        /// the permissions of existing files are kept.
+        chmod: Option<u32>,
+        #[clap(flatten)]
+        options: RenderOptions,
         #[clap(short, long)]
         /// Specifies the namespace in the context to use for rendering. If not specified
-        /// only GLOBAL namespace will be used.
-        namespace: Option<String>,
-        #[clap(long)]
-        /// By default, if there is no value for a variable name in the context nothing will
-        /// be rendered in place. This option enables rendering of missing variables.
-        show_missing: bool,
-        /// If true a diff of current file content and new rendered content will be displayed
+        /// only GLOBAL namespace will be used. Can be specified multiple times, the n-th
+        /// namespace is used for the n-th `--output-path`.
+        namespace: Vec<String>,
         #[clap(long)]
-        show_diff: bool,
+        /// Exit with a non-zero code after rendering if any variable had no value.
+        error_on_missing: bool,
         /// If true a header before each file will be printed
         #[clap(long)]
         show_headers: bool,
         /// If true no separator will be printed
         #[clap(long)]
         no_separator: bool,
+        #[clap(long)]
+        /// Use the output path and namespace declared in the front-matter of each template,
+        /// like `{@meta output = "~/.config/foo.conf", namespace = "dark" @}`. The
+        /// `--output-path` and `--namespace` options take precedence over the declarations.
+        from_meta: bool,
+        #[clap(long)]
+        /// Instead of writing the outputs save a unified diff of all changed outputs to this
+        /// file. Paths are relative to `/` so it can be applied with `patch -p0` run from `/`.
+        emit_patch: Option<PathBuf>,
+        #[clap(long)]
+        /// Instead of writing the outputs save a shell script that writes all changed outputs
+        /// to this file.
+        emit_script: Option<PathBuf>,
         #[clap(short, long)]
-        /// If true no changes will be made
-        dry_run: bool,
+        /// Render the templates on this many threads before writing them, `0` uses one thread
+        /// per CPU. The outputs are still written and reported in order.
+        jobs: Option<usize>,
+    },
+    /// Renders a template once for every namespace in the context.
+    RenderAll {
+        /// Input file to render.
+        template: PathBuf,
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(short, long)]
+        /// Location of the rendered files, `{namespace}` is replaced with the name of the
+        /// namespace, for example `~/.config/foo/themes/{namespace}.conf`.
+        output_pattern: String,
+        #[clap(long)]
+        /// Namespace that should not be rendered, can be specified multiple times.
+        skip: Vec<String>,
+        #[clap(flatten)]
+        options: RenderOptions,
     },
     /// Render specified context. If the context has no `renders` field this command has no effect.
     RenderContext {
-        /// Location of the context file to use for rendering.
-        context_file: PathBuf,
+        /// Location of the context file to use for rendering. If not specified the context file
+        /// is looked up in `$MOLD_CONTEXT`, `$XDG_CONFIG_HOME/mold/context.yaml` and
+        /// `~/.config/mold/context.yaml` in that order.
+        /// Can be specified multiple times to merge the contexts, later files take precedence.
+        context_file: Vec<PathBuf>,
+        #[clap(short, long)]
+        /// Specifies the namespace in the context to use for rendering. If not specified
+        /// the namespace recorded by the last `apply` of this context will be used, otherwise
+        /// only GLOBAL namespace will be used.
+        namespace: Option<String>,
+        #[clap(flatten)]
+        options: RenderOptions,
+        #[clap(long)]
+        /// Exit with a non-zero code after rendering if any variable had no value.
+        error_on_missing: bool,
+        #[clap(flatten)]
+        entry_filter: EntryFilter,
+        #[clap(long)]
+        /// Command to run with `$SHELL` once all renders succeeded if any output changed.
+        exec: Option<String>,
+        #[clap(long)]
+        /// Render all entries in symlink mode, the templates are rendered to `.mold-rendered`
+        /// next to the context file and the outputs are symlinks to the rendered files.
+        symlink: bool,
+        #[clap(long)]
+        /// Run the `on_change` hooks of entries whose output changed and the `post_render_all`
+        /// hook. Without this flag the hooks are only printed.
+        run_hooks: bool,
+        #[clap(long)]
+        /// Write a JSON manifest mapping every output to the SHA-256 hash of its rendered
+        /// content, the outputs can later be checked with `verify`.
+        manifest: Option<PathBuf>,
+        #[clap(long)]
+        /// Only list every template with its output resolved to absolute paths and whether the
+        /// template exists, nothing is rendered. Exits with a non-zero code if any template is
+        /// missing.
+        list: bool,
+        #[clap(long)]
+        /// Instead of writing the outputs save a unified diff of all changed outputs to this
+        /// file. Paths are relative to `/` so it can be applied with `patch -p0` run from `/`.
+        emit_patch: Option<PathBuf>,
+        #[clap(long)]
+        /// Instead of writing the outputs save a shell script that writes all changed outputs
+        /// to this file.
+        emit_script: Option<PathBuf>,
+        #[clap(long)]
+        /// Skip entries whose templates aren't UTF-8 text with a warning instead of copying or
+        /// failing to render them, entries with `verbatim: true` are still copied.
+        skip_binary: bool,
+        #[clap(short, long)]
+        /// Render the templates on this many threads before writing them, `0` uses one thread
+        /// per CPU. The outputs are still written and reported in order.
+        jobs: Option<usize>,
+    },
+    /// Renders specified context just like `render-context` and records the used context file
+    /// and namespace in the state file.
+    Apply {
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(short, long)]
+        /// Specifies the namespace in the context to use for rendering. If not specified
+        /// only GLOBAL namespace will be used.
+        namespace: Option<String>,
+        #[clap(long, conflicts_with_all = &["context-file", "namespace"])]
+        /// Apply the context file and namespace recorded by the last `apply` again.
+        last: bool,
+        #[clap(flatten)]
+        options: RenderOptions,
+        #[clap(long)]
+        /// Exit with a non-zero code after rendering if any variable had no value.
+        error_on_missing: bool,
+        #[clap(long)]
+        /// Command to run with `$SHELL` once all renders succeeded if any output changed.
+        exec: Option<String>,
+        #[clap(long)]
+        /// Render all entries in symlink mode, the templates are rendered to `.mold-rendered`
+        /// next to the context file and the outputs are symlinks to the rendered files.
+        symlink: bool,
+        #[clap(long)]
+        /// Run the `on_change` hooks of entries whose output changed and the `post_render_all`
+        /// hook. Without this flag the hooks are only printed.
+        run_hooks: bool,
+        #[clap(long)]
+        /// Write a JSON manifest mapping every output to the SHA-256 hash of its rendered
+        /// content, the outputs can later be checked with `verify`.
+        manifest: Option<PathBuf>,
+        #[clap(short, long)]
+        /// Render the templates on this many threads before writing them, `0` uses one thread
+        /// per CPU. The outputs are still written and reported in order.
+        jobs: Option<usize>,
+    },
+    /// Turns an existing config file into a template. Every value of a variable found in the
+    /// file is replaced with a tag of the variable, longer values first, and the template is
+    /// added to `renders` of the context with the file as the output.
+    Adopt {
+        /// Config file to adopt.
+        file: PathBuf,
+        #[clap(short, long)]
+        /// Location where the template is saved.
+        template: PathBuf,
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(short, long)]
+        /// Specifies the namespace whose variables are looked up in the file. Variables missing
+        /// in it are taken from the GLOBAL namespace.
+        namespace: Option<String>,
+        #[clap(short, long)]
+        /// Print the template and the number of substitutions of every variable without writing
+        /// anything.
+        dry_run: bool,
+        #[clap(short, long)]
+        /// Overwrite an existing template.
+        force: bool,
+    },
+    /// Writes a commented starter context file.
+    Init {
+        /// Location of the context file to create. If not specified it is created in
+        /// `$MOLD_CONTEXT`, `$XDG_CONFIG_HOME/mold/context.yaml` or
+        /// `~/.config/mold/context.yaml`, whichever is set first.
+        path: Option<PathBuf>,
+        #[clap(short, long)]
+        /// Overwrite an existing file.
+        force: bool,
+        #[clap(long)]
+        /// Directory of templates to generate the context from. The variables referenced by
+        /// the templates are added to the GLOBAL namespace with empty values.
+        from_dir: Option<PathBuf>,
+    },
+    /// Prints the context file and namespace recorded by the last `apply`.
+    Current,
+    /// Prints the resolved value of a single variable. Exits with a non-zero code if the variable
+    /// is not defined.
+    Get {
+        /// Name of the variable.
+        key: String,
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(short, long)]
+        /// Specifies the namespace in the context to look up the variable in. Variables missing
+        /// in it are taken from the GLOBAL namespace.
+        namespace: Option<String>,
+    },
+    /// Explains how a variable resolves: where its raw value is defined, how the variables it
+    /// references resolve and the final value. Lists similarly named variables if it is not
+    /// defined.
+    Explain {
+        /// Name of the variable.
+        key: String,
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(short, long)]
+        /// Specifies the namespace in the context to look up the variable in. Variables missing
+        /// in it are taken from the GLOBAL namespace.
+        namespace: Option<String>,
+    },
+    /// Lists every variable of the GLOBAL namespace that is defined in other namespaces as well,
+    /// showing the value of each namespace, which wins when rendering with it, next to the
+    /// GLOBAL value.
+    Shadowing {
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(long, arg_enum, default_value = "text")]
+        /// Format of the report.
+        format: PlanFormat,
+    },
+    /// Prints the names of all namespaces defined in the context.
+    Namespaces {
+        #[clap(flatten)]
+        context: ContextFiles,
+    },
+    /// Imports a color palette generated by pywal or a base16 scheme as a namespace with the
+    /// variables `background`, `foreground`, `cursor` and `color0` to `color15`. An existing
+    /// namespace with the same name is replaced.
+    ImportPalette {
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(long)]
+        /// Name of the namespace.
+        name: String,
+        #[clap(short, long)]
+        /// Location of the palette, like `~/.cache/wal/colors.json`.
+        file: PathBuf,
+        #[clap(long, arg_enum, default_value = "pywal")]
+        /// Format of the palette.
+        format: PaletteFormat,
+        #[clap(long)]
+        /// Print the namespace as YAML instead of writing it to the context file. The context
+        /// file is rewritten as a whole so comments in it are lost.
+        stdout: bool,
+    },
+    /// Imports resources like `*.color0: #1d2021` from an X resources file as a namespace. The
+    /// class and instance prefixes are stripped from the resource names.
+    ImportXresources {
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(long)]
+        /// Name of the namespace.
+        name: String,
+        #[clap(short, long)]
+        /// Location of the X resources file.
+        file: PathBuf,
+        #[clap(long)]
+        /// Add the resources to an existing namespace with the same name.
+        merge: bool,
+        #[clap(long, conflicts_with = "merge")]
+        /// Replace an existing namespace with the same name.
+        replace: bool,
+        #[clap(long)]
+        /// Print the namespace as YAML instead of writing it to the context file. The context
+        /// file is rewritten as a whole so comments in it are lost.
+        stdout: bool,
+    },
+    /// Shows for every entry of `renders` in the context whether its output is up to date with
+    /// the rendered template. Exits with a non-zero code if any output is out of date.
+    Status {
+        #[clap(flatten)]
+        context: ContextFiles,
         #[clap(short, long)]
         /// Specifies the namespace in the context to use for rendering. If not specified
+        /// the namespace recorded by the last `apply` of this context will be used, otherwise
         /// only GLOBAL namespace will be used.
         namespace: Option<String>,
         #[clap(long)]
@@ -76,21 +421,82 @@ enum Subcommand {
         /// be rendered in place. This option enables rendering of missing variables.
         show_missing: bool,
         #[clap(long)]
-        /// If true a diff of current file content and new rendered content will be displayed
-        show_diff: bool,
+        /// If true a diff of every output that differs from the rendered content will be
+        /// displayed
+        diff: bool,
+        #[clap(flatten)]
+        diff_options: DiffOptions,
+        #[clap(long)]
+        /// Expect the outputs to start with the header added by `--header`.
+        header: bool,
+    },
+    /// Checks that the outputs recorded in a manifest written with `--manifest` still have the
+    /// same content. Exits with a non-zero code if any output was modified or is missing.
+    Verify {
         #[clap(short, long)]
-        /// If true no changes will be made
-        dry_run: bool,
+        /// Location of the manifest.
+        manifest: PathBuf,
+    },
+    /// Prints the variables available for rendering as YAML. If a namespace is specified
+    /// the variables missing in it are taken from the GLOBAL namespace.
+    #[clap(alias = "dump-context")]
+    Dump {
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(short, long)]
+        /// Specifies the namespace whose variables should be printed. If not specified
+        /// only GLOBAL namespace will be used.
+        namespace: Option<String>,
+    },
+    /// Prints the variables available for rendering as shell commands exporting them as
+    /// environment variables, for example `eval "$(mold export -n nord --prefix THEME_)"`. The
+    /// names are uppercased with all characters that are not valid in shell identifiers replaced
+    /// with `_`.
+    Export {
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(short, long)]
+        /// Specifies the namespace whose variables should be exported. Variables missing in it
+        /// are taken from the GLOBAL namespace.
+        namespace: Option<String>,
+        #[clap(long, default_value = "")]
+        /// Prefix prepended to the name of every exported variable, like `THEME_`.
+        prefix: String,
+        #[clap(long, arg_enum, default_value = "sh")]
+        /// Syntax of the printed commands.
+        format: ExportFormat,
+        #[clap(long, use_value_delimiter = true)]
+        /// Comma separated names of the variables to export, all variables are exported if not
+        /// specified.
+        only: Vec<String>,
     },
     /// Prints a diff of current file content and newly rendered content.
+    /// Exits with a non-zero code if any output differs from the rendered template.
     Diff {
-        /// Template to render and diff.
-        template: PathBuf,
-        /// Location of the file to compare to.
-        output_path: PathBuf,
-        #[clap(short, long)]
-        /// Location of the context file to use for diffing.
-        context_file: PathBuf,
+        #[clap(required_unless_present = "all")]
+        /// Pairs of a template to render and the file to compare it to, like
+        /// `gtkrc-template ~/.gtkrc-2.0`. Use `-` to read a single template from standard input.
+        /// If the template is a directory every file in it and its subdirectories is compared
+        /// to the file at the same relative path in the output directory.
+        paths: Vec<PathBuf>,
+        #[clap(long)]
+        /// Suffix removed from the template file names when diffing a directory, for example
+        /// `.tmpl`.
+        strip_suffix: Option<String>,
+        #[clap(long)]
+        /// Extension added to, or replacing the extension of, the template file names when
+        /// diffing a directory.
+        output_extension: Option<String>,
+        #[clap(long, conflicts_with = "paths")]
+        /// Compare every entry of `renders` in the context with its output, entries with their
+        /// own namespace are rendered with it.
+        all: bool,
+        #[clap(flatten)]
+        diff_options: DiffOptions,
+        #[clap(flatten)]
+        line_endings: LineEndings,
+        #[clap(flatten)]
+        context: ContextFiles,
         #[clap(long)]
         /// By default, if there is no value for a variable name in the context nothing will
         /// be rendered in place. This option enables rendering of missing variables.
@@ -100,6 +506,36 @@ enum Subcommand {
         /// only GLOBAL namespace will be used.
         namespace: Option<String>,
     },
+    /// Renders a template with two namespaces and displays a diff of the results.
+    DiffNs {
+        /// Template to render and diff. Use `-` to read the template from standard input. If not
+        /// specified every template in `renders` of the context is compared and a summary is
+        /// printed.
+        template: Option<PathBuf>,
+        #[clap(flatten)]
+        context: ContextFiles,
+        #[clap(long)]
+        /// Namespace of the old side of the diff.
+        from: String,
+        #[clap(long)]
+        /// Namespace of the new side of the diff.
+        to: String,
+        #[clap(flatten)]
+        diff_options: DiffOptions,
+        #[clap(long)]
+        /// By default, if there is no value for a variable name in the context nothing will
+        /// be rendered in place. This option enables rendering of missing variables.
+        show_missing: bool,
+    },
+    /// Prints the tokens the template is parsed into with their locations, useful to find out
+    /// why a template renders unexpectedly.
+    Tokens {
+        /// Template to parse. Use `-` to read the template from standard input.
+        template: PathBuf,
+        #[clap(long, arg_enum, default_value = "text")]
+        /// Format of the printed tokens.
+        format: PlanFormat,
+    },
 }
 
 struct Line(Option<usize>);
@@ -113,18 +549,286 @@ impl std::fmt::Display for Line {
     }
 }
 
-fn diff<W: io::Write>(writer: &mut W, a: &str, b: &str) -> io::Result<()> {
+/// Normalization of the line breaks of the rendered content, applied before it's written or
+/// diffed so that diffs show what ends up on disk.
+#[derive(Debug, Default, Clone, Copy, clap::Args)]
+struct LineEndings {
+    #[clap(long, default_value = "keep")]
+    /// Line endings of the rendered content, one of `lf`, `crlf`, `native` or `keep`.
+    newline: Newline,
+    #[clap(long, default_value = "keep")]
+    /// Line breaks at the end of the rendered content, `always` ends it with exactly one,
+    /// `never` removes them and `keep` leaves them as they are.
+    final_newline: FinalNewline,
+}
+
+impl LineEndings {
+    /// Returns the line endings set by `entry`, the ones it doesn't set are taken from `self`.
+    fn of_entry(self, entry: &RenderEntry) -> Self {
+        Self {
+            newline: entry.newline().unwrap_or(self.newline),
+            final_newline: entry.final_newline().unwrap_or(self.final_newline),
+        }
+    }
+
+    fn apply(self, content: &str) -> String {
+        self.newline.apply(&self.final_newline.apply(content))
+    }
+}
+
+/// Glob patterns selecting the entries of the context that are rendered.
+#[derive(Debug, Default, Clone, clap::Args)]
+struct EntryFilter {
+    #[clap(long)]
+    /// Only render entries whose template matches this glob pattern, can be specified multiple
+    /// times. A pattern without a `/` is matched against the file name of the template, otherwise
+    /// against its resolved path. `*` and `?` don't match `/` while `**` does.
+    only: Vec<String>,
+    #[clap(long)]
+    /// Skip entries whose template matches this glob pattern, can be specified multiple times.
+    /// Takes precedence over `--only`.
+    skip: Vec<String>,
+}
+
+impl EntryFilter {
+    /// Checks whether the entry of the resolved `template` is selected by the patterns.
+    fn selects(&self, template: &Path) -> bool {
+        let path = template.to_string_lossy();
+        let name = template
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let matches = |pattern: &String| {
+            if pattern.contains('/') {
+                glob_matches(&shellexpand::tilde(pattern), &path)
+            } else {
+                glob_matches(pattern, &name)
+            }
+        };
+        (self.only.is_empty() || self.only.iter().any(matches)) && !self.skip.iter().any(matches)
+    }
+}
+
+/// Matches the whole `text` against a glob `pattern`. `*` matches any characters but `/`, `**`
+/// matches any characters including `/` and `?` matches a single character other than `/`.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern {
+            [] => text.is_empty(),
+            ['*', '*', rest @ ..] => {
+                // `**/` matches no directories as well
+                (0..=text.len()).any(|i| matches(rest, &text[i..]))
+                    || rest.first() == Some(&'/') && matches(&rest[1..], text)
+            }
+            ['*', rest @ ..] => (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != '/')
+                .any(|i| matches(rest, &text[i..])),
+            ['?', rest @ ..] => {
+                matches!(text.first(), Some(c) if *c != '/') && matches(rest, &text[1..])
+            }
+            [c, rest @ ..] => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+    let pattern: Vec<_> = pattern.chars().collect();
+    let text: Vec<_> = text.chars().collect();
+    matches(&pattern, &text)
+}
+
+/// Context files of the subcommands that read a context.
+#[derive(Debug, clap::Args)]
+struct ContextFiles {
+    #[clap(short, long)]
+    /// Location of the context file. If not specified the context file is looked up in
+    /// `$MOLD_CONTEXT`, `$XDG_CONFIG_HOME/mold/context.yaml` and `~/.config/mold/context.yaml` in
+    /// that order. Can be specified multiple times to merge the contexts, later files take
+    /// precedence. Subcommands that modify the context accept a single file.
+    context_file: Vec<PathBuf>,
+}
+
+impl ContextFiles {
+    /// Returns the expanded paths of the context files, or the context file found in the default
+    /// locations if none were specified.
+    fn paths(self) -> Vec<PathBuf> {
+        context_file_paths(self.context_file)
+    }
+
+    /// Returns the context file of a subcommand that modifies it, exits if more than one file
+    /// was specified.
+    fn single(self) -> Option<PathBuf> {
+        if self.context_file.len() > 1 {
+            exit!("this subcommand modifies the context and accepts a single context file");
+        }
+        self.context_file.into_iter().next()
+    }
+}
+
+/// Options shared by the subcommands that render and write files.
+#[derive(Debug, clap::Args)]
+struct RenderOptions {
+    #[clap(long)]
+    /// By default, if there is no value for a variable name in the context nothing will
+    /// be rendered in place. This option enables rendering of missing variables.
+    show_missing: bool,
+    #[clap(long)]
+    /// If true a diff of current file content and new rendered content will be displayed
+    show_diff: bool,
+    #[clap(flatten)]
+    diff_options: DiffOptions,
+    #[clap(flatten)]
+    line_endings: LineEndings,
+    #[clap(short, long)]
+    /// If true no changes will be made
+    dry_run: bool,
+    #[clap(long, arg_enum, default_value = "text")]
+    /// Format of the plan printed in dry run mode.
+    format: PlanFormat,
+    #[clap(short, long)]
+    /// Overwrite outputs that were edited outside of mold since they were last rendered.
+    force: bool,
+    #[clap(short, long, conflicts_with = "force")]
+    /// Ask before overwriting outputs that were edited outside of mold since they were last
+    /// rendered.
+    interactive: bool,
+    #[clap(long)]
+    /// Prepend a comment marking the saved files as generated by mold. The comment leader
+    /// is derived from the output extension and defaults to `#`.
+    header: bool,
+    #[clap(long)]
+    /// Write a JSON report of the run to this file listing the outcome and missing variables
+    /// of every template together with the counts of the outcomes.
+    summary_json: Option<PathBuf>,
+    #[clap(long)]
+    /// Stop at the first template that fails to render instead of rendering the remaining
+    /// ones.
+    fail_fast: bool,
+}
+
+/// How diffs are displayed.
+#[derive(Debug, Clone, Copy, clap::Args)]
+struct DiffOptions {
+    #[clap(long, default_value_t = 3)]
+    /// Number of unchanged lines displayed around every change in diffs.
+    diff_context: usize,
+    #[clap(long)]
+    /// Display the entire file with the changes marked in diffs instead of only the changed
+    /// hunks.
+    diff_full: bool,
+    #[clap(long, arg_enum, default_value = "inline")]
+    /// Layout of diffs. Side-by-side diffs fall back to the inline layout if standard output is
+    /// not a terminal or the terminal is too narrow, unless `--width` is given.
+    diff_format: DiffFormat,
+    #[clap(long)]
+    /// Width of side-by-side diffs, defaults to the width of the terminal.
+    width: Option<usize>,
+    #[clap(long)]
+    /// Display only the number of added and removed lines of every diff and the total instead
+    /// of the diffs.
+    stat_only: bool,
+}
+
+/// Numbers of added and removed lines of a diff.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct DiffStat {
+    insertions: usize,
+    deletions: usize,
+}
+
+impl DiffStat {
+    /// Counts the changes of the same line diff that is displayed by `diff`.
+    fn of(old: &str, new: &str) -> Self {
+        let mut stat = Self::default();
+        for change in similar::TextDiff::from_lines(old, new).iter_all_changes() {
+            match change.tag() {
+                ChangeTag::Insert => stat.insertions += 1,
+                ChangeTag::Delete => stat.deletions += 1,
+                ChangeTag::Equal => {}
+            }
+        }
+        stat
+    }
+
+    fn is_empty(&self) -> bool {
+        self.insertions == 0 && self.deletions == 0
+    }
+
+    fn lines(&self) -> usize {
+        self.insertions + self.deletions
+    }
+}
+
+impl std::fmt::Display for DiffStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            format!("+{}", self.insertions).green(),
+            format!("-{}", self.deletions).red()
+        )
+    }
+}
+
+/// Prints the total of `stats` if more than one file changed.
+fn print_diff_total(stats: impl IntoIterator<Item = DiffStat>) {
+    let (files, total) = stats.into_iter().filter(|stat| !stat.is_empty()).fold(
+        (0, DiffStat::default()),
+        |(files, total), stat| {
+            let total = DiffStat {
+                insertions: total.insertions + stat.insertions,
+                deletions: total.deletions + stat.deletions,
+            };
+            (files + 1, total)
+        },
+    );
+    if files > 1 {
+        println!("{} files changed: {}", files, total);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+enum DiffFormat {
+    /// Removed and added lines one after another.
+    Inline,
+    /// Old content on the left and new content on the right.
+    SideBySide,
+}
+
+impl DiffOptions {
+    /// Returns the width of side-by-side diffs or `None` if the inline layout should be used.
+    fn side_by_side_width(&self) -> Option<usize> {
+        if self.diff_format != DiffFormat::SideBySide {
+            return None;
+        }
+        self.width
+            .or_else(|| {
+                io::stdout()
+                    .is_terminal()
+                    .then(side_by_side::terminal_width)
+                    .flatten()
+            })
+            .filter(|width| *width >= side_by_side::MIN_WIDTH)
+    }
+}
+
+fn diff<W: io::Write>(writer: &mut W, a: &str, b: &str, options: DiffOptions) -> io::Result<()> {
     let diff = similar::TextDiff::from_lines(a, b);
-    for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
+    let groups = if options.diff_full {
+        vec![diff.ops().to_vec()]
+    } else {
+        diff.grouped_ops(options.diff_context)
+    };
+    if let Some(width) = options.side_by_side_width() {
+        return side_by_side::write(writer, &diff, &groups, width);
+    }
+    for (idx, group) in groups.iter().enumerate() {
         if idx > 0 {
             writer.write_all(format!("{:-^1$}\n", "-", 80).as_bytes())?;
         }
         for op in group {
             for change in diff.iter_inline_changes(op) {
                 let (sign, sign_color) = match change.tag() {
-                    ChangeTag::Delete => ("-".red(), Color::Red),
-                    ChangeTag::Insert => ("+".green(), Color::Green),
-                    ChangeTag::Equal => continue,
+                    ChangeTag::Delete => ("-".red(), Some(Color::Red)),
+                    ChangeTag::Insert => ("+".green(), Some(Color::Green)),
+                    ChangeTag::Equal => (" ".normal(), None),
                 };
                 writer.write_all(
                     format!(
@@ -136,13 +840,14 @@ fn diff<W: io::Write>(writer: &mut W, a: &str, b: &str) -> io::Result<()> {
                     .as_bytes(),
                 )?;
                 for (emphasized, value) in change.iter_strings_lossy() {
-                    if emphasized {
-                        writer.write_all(
-                            format!("{}", value.color(sign_color).underline().on_black())
-                                .as_bytes(),
-                        )?;
-                    } else {
-                        writer.write_all(format!("{}", value.color(sign_color)).as_bytes())?;
+                    match sign_color {
+                        Some(color) if emphasized => writer.write_all(
+                            format!("{}", value.color(color).underline().on_black()).as_bytes(),
+                        )?,
+                        Some(color) => {
+                            writer.write_all(format!("{}", value.color(color)).as_bytes())?
+                        }
+                        None => writer.write_all(value.as_bytes())?,
                     }
                 }
                 if change.missing_newline() {
@@ -154,12 +859,109 @@ fn diff<W: io::Write>(writer: &mut W, a: &str, b: &str) -> io::Result<()> {
     Ok(())
 }
 
-fn expand(path: &Path) -> PathBuf {
-    PathBuf::from(shellexpand::tilde(&path.to_string_lossy()).to_string())
+/// Returns the path of the relative `template` as an entry of `renders` of `context_file`, where
+/// relative templates are relative to the directory of the context file. Templates outside of
+/// that directory are absolute.
+fn render_key(context_file: &Path, template: &Path) -> PathBuf {
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let template = absolute(template);
+    let context_file = absolute(context_file);
+    match context_file
+        .parent()
+        .and_then(|dir| template.strip_prefix(dir).ok())
+    {
+        Some(relative) => relative.to_path_buf(),
+        None => template,
+    }
+}
+
+/// Path that stands for standard input or standard output depending on where it is used.
+const STDIO_PATH: &str = "-";
+
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO_PATH)
+}
+
+/// Expands `template` and resolves it against the template root if it's relative, standard
+/// input is left as is.
+fn template_path(mold: &Mold, template: &Path) -> PathBuf {
+    if is_stdio(template) {
+        return template.to_path_buf();
+    }
+    mold.resolve_template(&expand_path(template))
+}
+
+fn render_input(
+    mold: &Mold,
+    template: &Path,
+    namespace: Option<&str>,
+    show_missing: bool,
+) -> anyhow::Result<Rendered> {
+    let start = Instant::now();
+    let rendered = if is_stdio(template) {
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .context("failed to read template from standard input")?;
+        mold.render_str(&input, namespace, show_missing)
+            .context("failed to render template from standard input")
+    } else {
+        mold.render_file_with_missing(template, namespace, show_missing)
+    };
+    if rendered.is_ok() {
+        info!(
+            "rendered `{}` in {:.2?}",
+            template.display(),
+            start.elapsed()
+        );
+    }
+    rendered
+}
+
+/// Renders the `templates` with their namespaces on `jobs` threads ahead of writing them,
+/// nothing is rendered ahead without `--jobs`.
+fn prerender<'a, P: AsRef<Path>>(
+    mold: &Mold,
+    templates: impl Iterator<Item = (P, Option<&'a str>)>,
+    jobs: Option<usize>,
+    show_missing: bool,
+) -> Prerendered {
+    let jobs = match jobs {
+        Some(jobs) => jobs,
+        None => return Prerendered::default(),
+    };
+    let templates = templates
+        .filter(|(template, _)| !is_stdio(template.as_ref()))
+        .map(|(template, namespace)| {
+            (
+                template_path(mold, template.as_ref()),
+                namespace.map(str::to_string),
+            )
+        })
+        .collect();
+    match Prerendered::render(mold, templates, jobs, show_missing) {
+        Ok(prerendered) => prerendered,
+        Err(e) => exit!("failed to start {} rendering threads - {:?}", jobs, e),
+    }
 }
 
-fn display_diff(template: &Path, output: &Path, namespace: Option<&str>, rendered: &str) {
-    if let Ok(loaded) = std::fs::read_to_string(&output) {
+/// Displays a diff of the file at `output` and the `rendered` content with the values of secret
+/// variables redacted, returns the numbers of changed lines.
+fn display_diff(
+    mold: &Mold,
+    template: &Path,
+    output: &Path,
+    namespace: Option<&str>,
+    rendered: &str,
+    options: DiffOptions,
+) -> DiffStat {
+    let loaded = match std::fs::read_to_string(output) {
+        Ok(loaded) => loaded,
+        Err(_) => return DiffStat::default(),
+    };
+    let stat = DiffStat::of(&loaded, rendered);
+    if !options.stat_only {
+        let secrets = mold.secrets(namespace);
         println!("{:=^1$}", "=", 80);
         println!("|{: ^1$}DIFF", " ", 37);
         println!("| Template:  {}", template.to_string_lossy().bold());
@@ -168,162 +970,3085 @@ fn display_diff(template: &Path, output: &Path, namespace: Option<&str>, rendere
             "| Namespace: {}",
             namespace.unwrap_or(mold::GLOBAL_NS).bold()
         );
-        let _ = diff(&mut io::stdout(), &loaded, rendered);
+        let _ = diff(
+            &mut io::stdout(),
+            &secrets.redact(&loaded),
+            &secrets.redact(rendered),
+            options,
+        );
     }
+    if !stat.is_empty() {
+        println!("{}: {}", output.display(), stat);
+    }
+    stat
+}
+
+/// Result of comparing a rendered template with its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DiffStatus {
+    Unchanged,
+    Differs,
+    Missing,
+    Failed,
 }
 
+/// Renders `template` and displays a diff with the file at `output_path` if they differ. With
+/// a `header` comment leader the rendered content gets the header added by `--header`, the line
+/// endings are normalized afterwards. Returns the status together with the numbers of
+/// changed lines.
+#[allow(clippy::too_many_arguments)]
 fn diff_template(
     mold: &Mold,
     template: &Path,
     output_path: &Path,
     namespace: Option<&str>,
     show_missing: bool,
-) {
-    let template = expand(template);
-    match mold.render_file(&template, namespace, show_missing) {
-        Ok(rendered) => {
-            let output_path = expand(output_path);
-            display_diff(&template, &output_path, namespace, &rendered);
+    header: Option<&str>,
+    line_endings: LineEndings,
+    diff_options: DiffOptions,
+) -> (DiffStatus, DiffStat) {
+    let template = template_path(mold, template);
+    let output_path = expand_path(output_path);
+    let rendered = match render_input(mold, &template, namespace, show_missing) {
+        Ok(rendered) => match header {
+            Some(leader) => header::with_header(&rendered.output, leader, &template, namespace),
+            None => rendered.output,
+        },
+        Err(e) => {
+            eprintln!("{:?}", e);
+            return (DiffStatus::Failed, DiffStat::default());
+        }
+    };
+    let rendered = line_endings.apply(&rendered);
+    let (status, stat) = match std::fs::read_to_string(&output_path) {
+        Ok(current) if current == rendered => (DiffStatus::Unchanged, DiffStat::default()),
+        Ok(_) => (
+            DiffStatus::Differs,
+            display_diff(
+                mold,
+                &template,
+                &output_path,
+                namespace,
+                &rendered,
+                diff_options,
+            ),
+        ),
+        Err(_) => (DiffStatus::Missing, DiffStat::default()),
+    };
+    if matches!(status, DiffStatus::Unchanged | DiffStatus::Missing) {
+        let describe = match status {
+            DiffStatus::Unchanged => "unchanged".normal(),
+            _ => "missing".red(),
+        };
+        println!(
+            "{:<10} {} -> {}",
+            describe,
+            template.display(),
+            output_path.display()
+        );
+    }
+    (status, stat)
+}
+
+/// Renders `template` with both namespaces and displays the diff of the results. Returns the
+/// numbers of changed lines or `None` if the template failed to render.
+fn diff_namespaces(
+    mold: &Mold,
+    template: &Path,
+    from: &str,
+    to: &str,
+    show_missing: bool,
+    diff_options: DiffOptions,
+) -> Option<DiffStat> {
+    let template = template_path(mold, template);
+    let render = |namespace| match render_input(mold, &template, Some(namespace), show_missing) {
+        Ok(rendered) => Some(rendered.output),
+        Err(e) => {
+            let e = e.context(format!("failed to render with namespace `{}`", namespace));
+            eprintln!("{:?}", e);
+            None
+        }
+    };
+    let old = render(from)?;
+    let new = render(to)?;
+    let stat = DiffStat::of(&old, &new);
+    if !stat.is_empty() {
+        if !diff_options.stat_only {
+            println!("{:=^1$}", "=", 80);
+            println!("|{: ^1$}DIFF", " ", 37);
+            println!("| Template:  {}", template.to_string_lossy().bold());
+            println!("| From:      {} {}", from.bold(), "(-)".red());
+            println!("| To:        {} {}", to.bold(), "(+)".green());
+            let _ = diff(
+                &mut io::stdout(),
+                &mold.secrets(Some(from)).redact(&old),
+                &mold.secrets(Some(to)).redact(&new),
+                diff_options,
+            );
         }
-        Err(e) => eprintln!("failed to render file `{}` - {:?}", template.display(), e),
+        println!("{}: {}", template.display(), stat);
     }
+    Some(stat)
 }
 
 struct DisplayOptions {
     show_diff: bool,
+    diff: DiffOptions,
     show_missing: bool,
+    /// Print a warning for every variable of a template that had no value.
+    warn_missing: bool,
     show_headers: bool,
     show_separator: bool,
+    /// Suppresses informational output, errors and diffs are still printed.
+    quiet: bool,
 }
 
-fn render_template(
-    mold: &Mold,
-    namespace: Option<&str>,
-    template: &Path,
-    output_path: Option<&Path>,
-    display_options: &DisplayOptions,
+#[derive(Clone, Copy)]
+struct WriteOptions<'a> {
     dry_run: bool,
-) {
-    let template = expand(template);
-    match mold.render_file(&template, namespace, display_options.show_missing) {
-        Ok(rendered) => {
-            let len = template.to_string_lossy().len() + 6;
-            let line = "-".repeat(len);
-            if let Some(output_path) = output_path {
-                let output_path = expand(output_path);
-                if display_options.show_diff {
-                    display_diff(&template, &output_path, namespace, &rendered);
-                }
-                println!("saving {} to {}", template.display(), output_path.display());
-                if !dry_run {
-                    if let Err(e) = std::fs::write(&output_path, rendered.as_bytes()) {
-                        eprintln!(
-                            "failed to save rendered file `{}` to `{}` - {:?}",
-                            template.display(),
-                            output_path.display(),
-                            e
-                        );
-                    }
-                }
-            } else {
-                if display_options.show_separator {
-                    println!("{:=^1$}", "=", 80);
-                }
-                if display_options.show_headers {
-                    println!("File: {}\n{}", template.display(), line);
-                }
-                println!("{}", rendered);
-            }
-        }
-        Err(e) => eprintln!("failed to render file `{}` - {:?}", template.display(), e),
+    force: bool,
+    interactive: bool,
+    /// Render all entries of the context in symlink mode.
+    symlink: bool,
+    /// Permissions set on the written files.
+    permissions: Option<u32>,
+    /// Prepend a header marking the written files as generated.
+    header: bool,
+    /// Comment leader of the header, derived from the output extension if not specified.
+    header_comment: Option<&'a str>,
+    /// Run the hooks of the context, otherwise they are only reported.
+    run_hooks: bool,
+    /// Write the outputs with `elevate_command`.
+    privileged: bool,
+    elevate_command: &'a str,
+    /// Copy the template to the output without rendering it.
+    verbatim: bool,
+    /// Skip templates that aren't text instead of copying or failing to render them.
+    skip_binary: bool,
+    /// Line endings the rendered content is converted to.
+    line_endings: LineEndings,
+    /// Stop rendering after the first failed template.
+    fail_fast: bool,
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(path: &Path, _: u32) -> io::Result<()> {
+    warn!(
+        "setting permissions of `{}` is not supported on this platform",
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+fn current_permissions(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .ok()
+        .map(|metadata| metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn current_permissions(_: &Path) -> Option<u32> {
+    None
+}
+
+/// Default permissions of new privileged outputs.
+const PRIVILEGED_MODE: u32 = 0o644;
+
+/// Arguments of the elevated command that writes the file `temp` to `output` with `mode`, see
+/// [`unix_install_args`] and [`windows_install_args`].
+fn install_args(temp: &Path, output: &Path, mode: u32) -> Vec<std::ffi::OsString> {
+    if cfg!(unix) {
+        unix_install_args(temp, output, mode)
+    } else {
+        windows_install_args(temp, output)
     }
 }
 
-fn main() {
-    let opts = Opts::parse();
+/// Arguments that install the file `temp` as `output` with `mode`. The file is installed next to
+/// the output and then moved over it, so the output is replaced atomically.
+fn unix_install_args(temp: &Path, output: &Path, mode: u32) -> Vec<std::ffi::OsString> {
+    vec![
+        "sh".into(),
+        "-c".into(),
+        r#"install -m "$1" -- "$2" "$3.mold-tmp" && mv -f -- "$3.mold-tmp" "$3""#.into(),
+        "sh".into(),
+        format!("{:o}", mode).into(),
+        temp.into(),
+        output.into(),
+    ]
+}
 
-    match opts.command {
-        Subcommand::Render {
-            context_file,
-            templates,
-            output_path,
-            namespace,
-            show_missing,
-            show_diff,
-            show_headers,
-            no_separator,
-            dry_run,
-        } => {
-            let mold = match Mold::new(&context_file) {
-                Ok(mold) => mold,
-                Err(e) => exit!("failed to initialize mold - {:?}", e),
-            };
-            let display_opts = DisplayOptions {
-                show_missing,
-                show_diff,
-                show_headers,
-                show_separator: !no_separator,
-            };
+/// Arguments that copy the file `temp` over `output`. Windows can't move a file over one that is
+/// open in another program, so the content is replaced in place instead and the mode is ignored
+/// like for other outputs.
+fn windows_install_args(temp: &Path, output: &Path) -> Vec<std::ffi::OsString> {
+    vec![
+        "cmd".into(),
+        "/C".into(),
+        "copy".into(),
+        "/Y".into(),
+        temp.into(),
+        output.into(),
+    ]
+}
 
-            templates.into_iter().for_each(|template| {
-                render_template(
-                    &mold,
-                    namespace.as_deref(),
-                    &template,
-                    output_path.as_deref(),
-                    &display_opts,
-                    dry_run,
-                );
-            });
-        }
-        Subcommand::RenderContext {
-            context_file,
+/// Writes `content` to `output` with `elevate_command`, only this step runs with elevated
+/// privileges. The content is saved to a temporary file which is then installed with
+/// [`install_args`]. The permissions of an existing output are kept if `mode` is not specified.
+fn write_privileged(
+    output: &Path,
+    content: &[u8],
+    mode: Option<u32>,
+    elevate_command: &str,
+) -> io::Result<()> {
+    let mode = mode
+        .or_else(|| current_permissions(output))
+        .unwrap_or(PRIVILEGED_MODE);
+    let mut words = elevate_command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::other("the elevate command is empty"))?;
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // the file is created exclusively and only readable by the current user in a private
+    // directory, so it can't be swapped for another file or read before it's installed
+    let dir = tempfile::Builder::new().prefix("mold-").tempdir()?;
+    let mut temp = tempfile::Builder::new()
+        .prefix(&file_name)
+        .tempfile_in(dir.path())?;
+    temp.write_all(content)?;
+    let temp = temp.into_temp_path();
+    debug!(
+        "writing `{}` with `{}` from `{}`",
+        output.display(),
+        elevate_command,
+        temp.display()
+    );
+    let status = std::process::Command::new(program)
+        .args(words)
+        .args(install_args(&temp, output, mode))
+        .status();
+    match status? {
+        status if status.success() => Ok(()),
+        status => Err(io::Error::other(format!(
+            "`{}` failed with {}",
+            elevate_command, status
+        ))),
+    }
+}
+
+/// Checks whether the output was edited outside of mold since mold last wrote it.
+fn is_modified(state: &StateFile, output_path: &Path) -> bool {
+    match (
+        state.state().output_hash(output_path),
+        std::fs::read(output_path),
+    ) {
+        (Some(hash), Ok(current)) => hash != state::hash(&current),
+        _ => false,
+    }
+}
+
+fn confirm(prompt: &str) -> bool {
+    eprint!("{} [y/N] ", prompt);
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Decides whether a rendered file can be written to `output_path` without losing changes
+/// made outside of mold, otherwise returns the action taken instead. A dry run never asks for
+/// confirmation, the conflict is reported in the plan.
+fn overwrite_conflict(
+    state: &StateFile,
+    output_path: &Path,
+    write_options: &WriteOptions<'_>,
+) -> Option<Action> {
+    if write_options.force || !is_modified(state, output_path) {
+        return None;
+    }
+    if write_options.interactive {
+        if write_options.dry_run {
+            return Some(Action::Conflict);
+        }
+        let overwrite = confirm(&format!(
+            "`{}` was modified since it was last rendered, overwrite?",
+            output_path.display()
+        ));
+        return (!overwrite).then_some(Action::Skip);
+    }
+    warn!(
+        "skipping `{}` as it was modified since it was last rendered, use --force to overwrite",
+        output_path.display()
+    );
+    Some(Action::Skip)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Create,
+    Update,
+    Unchanged,
+    /// The output was modified outside of mold and won't be overwritten.
+    Skip,
+    /// The output was modified outside of mold, in a dry run with `--interactive` overwriting
+    /// it would have to be confirmed.
+    Conflict,
+    /// The rendered content was printed instead of being saved.
+    Print,
+    Fail,
+}
+
+impl Action {
+    /// Describes the action as planned in dry run mode.
+    fn describe(&self) -> &'static str {
+        match self {
+            Action::Create => "would create",
+            Action::Update => "would update",
+            Action::Unchanged => "unchanged",
+            Action::Skip => "would skip",
+            Action::Conflict => "would ask",
+            Action::Print => "printed",
+            Action::Fail => "failed",
+        }
+    }
+}
+
+/// Result of rendering a single template.
+#[derive(Debug, Serialize)]
+struct RenderResult {
+    template: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<PathBuf>,
+    action: Action,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lines_changed: Option<usize>,
+    /// Symlink pointing to the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link: Option<PathBuf>,
+    /// Hash of the rendered content.
+    #[serde(skip)]
+    hash: Option<String>,
+    /// Namespace the template was rendered with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+    /// Variables that had no value.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    missing: Vec<String>,
+    /// Numbers of changed lines of the displayed diff.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff_stat: Option<DiffStat>,
+    /// Rendered content that would have been written in dry run mode.
+    #[serde(skip)]
+    content: Option<String>,
+}
+
+impl RenderResult {
+    fn new(template: &Path, output: Option<&Path>, action: Action) -> Self {
+        Self {
+            template: template.to_path_buf(),
+            output: output.map(Path::to_path_buf),
+            action,
+            lines_changed: None,
+            link: None,
+            hash: None,
+            namespace: None,
+            missing: vec![],
+            diff_stat: None,
+            content: None,
+        }
+    }
+
+    /// Whether the output content changed, or would change in dry run mode.
+    fn changed(&self) -> bool {
+        matches!(self.action, Action::Create | Action::Update)
+    }
+}
+
+fn count_changed_lines(old: &str, new: &str) -> usize {
+    similar::TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .filter(|change| change.tag() != ChangeTag::Equal)
+        .count()
+}
+
+/// Writes `content` of `template` to `output_path` with `permissions` and records it in the
+/// state, the action of `result` is set to failed if the output couldn't be written.
+#[allow(clippy::too_many_arguments)]
+fn save_output(
+    template: &Path,
+    output_path: &Path,
+    content: &[u8],
+    permissions: Option<u32>,
+    display_options: &DisplayOptions,
+    write_options: &WriteOptions<'_>,
+    state: &mut StateFile,
+    result: &mut RenderResult,
+) {
+    if result.changed() && !display_options.quiet {
+        println!("saving {} to {}", template.display(), output_path.display());
+    }
+    let written = if write_options.privileged {
+        write_privileged(
+            output_path,
+            content,
+            permissions,
+            write_options.elevate_command,
+        )
+    } else {
+        std::fs::write(output_path, content).and_then(|_| match permissions {
+            Some(mode) => set_permissions(output_path, mode),
+            None => Ok(()),
+        })
+    };
+    match written {
+        Ok(_) => {
+            debug!(
+                "wrote {} bytes to `{}`",
+                content.len(),
+                output_path.display()
+            );
+            state.record_output(output_path, content)
+        }
+        Err(e) => {
+            eprintln!(
+                "failed to save rendered file `{}` to `{}` - {:?}",
+                template.display(),
+                output_path.display(),
+                e
+            );
+            result.action = Action::Fail;
+        }
+    }
+}
+
+/// Whether `template` is copied to its output as is instead of being rendered. Binary files are
+/// never rendered even if the entry isn't `verbatim`.
+fn is_verbatim(template: &Path, verbatim: bool) -> bool {
+    if verbatim {
+        return true;
+    }
+    match mold::is_binary_file(template) {
+        Ok(true) => {
+            warn!(
+                "`{}` looks binary, copying it without rendering",
+                template.display()
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Copies `template` to `output_path` without rendering it. The permissions of the template are
+/// kept unless other permissions are specified, headers are never added.
+fn copy_template(
+    mold: &Mold,
+    template: &Path,
+    output_path: &Path,
+    display_options: &DisplayOptions,
+    write_options: &WriteOptions<'_>,
+    state: &mut StateFile,
+) -> RenderResult {
+    let mut result = RenderResult::new(template, Some(output_path), Action::Create);
+    let content = match std::fs::read(template) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("failed to read file `{}` - {:?}", template.display(), e);
+            result.action = Action::Fail;
+            return result;
+        }
+    };
+    // only text is diffed
+    let text = std::str::from_utf8(&content)
+        .ok()
+        .filter(|_| !mold::is_binary(&content));
+    if let (true, Some(text)) = (display_options.show_diff, text) {
+        let stat = display_diff(
+            mold,
+            template,
+            output_path,
+            None,
+            text,
+            display_options.diff,
+        );
+        result.diff_stat = Some(stat).filter(|stat| !stat.is_empty());
+    }
+    result.hash = Some(state::hash(&content));
+    if let Ok(current) = std::fs::read(output_path) {
+        if current == content {
+            result.action = Action::Unchanged;
+        } else {
+            result.action = Action::Update;
+            if let (Ok(current), Some(text)) = (std::str::from_utf8(&current), text) {
+                result.lines_changed = Some(count_changed_lines(current, text));
+            }
+        }
+    }
+    if !result.changed() {
+        info!("`{}` is up to date", output_path.display());
+    }
+    if result.changed() {
+        if let Some(action) = overwrite_conflict(state, output_path, write_options) {
+            info!("skipped writing `{}`", output_path.display());
+            result.action = action;
+            return result;
+        }
+    }
+    if write_options.dry_run {
+        result.content = text.map(str::to_string);
+        return result;
+    }
+    save_output(
+        template,
+        output_path,
+        &content,
+        write_options
+            .permissions
+            .or_else(|| current_permissions(template)),
+        display_options,
+        write_options,
+        state,
+        &mut result,
+    );
+    result
+}
+
+/// Prints a warning for every variable of `template` that had no value when rendered with
+/// `namespace`, the names are already deduplicated.
+fn warn_missing(template: &Path, namespace: Option<&str>, missing: &[String]) {
+    let searched = match namespace.filter(|namespace| *namespace != mold::GLOBAL_NS) {
+        Some(namespace) => format!("namespace `{}` or {}", namespace, mold::GLOBAL_NS),
+        None => mold::GLOBAL_NS.to_string(),
+    };
+    for name in missing {
+        warn!(
+            "{}: variable `{}` not found in {}",
+            template.display(),
+            name,
+            searched
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_template(
+    mold: &Mold,
+    namespace: Option<&str>,
+    template: &Path,
+    output_path: Option<&Path>,
+    prerendered: &Prerendered,
+    display_options: &DisplayOptions,
+    write_options: &WriteOptions<'_>,
+    state: &mut StateFile,
+) -> RenderResult {
+    let template = template_path(mold, template);
+    if let Some(output_path) = output_path.filter(|path| !is_stdio(path)) {
+        if !is_stdio(&template) && is_verbatim(&template, write_options.verbatim) {
+            return copy_template(
+                mold,
+                &template,
+                &expand_path(output_path),
+                display_options,
+                write_options,
+                state,
+            );
+        }
+    }
+    let rendered = match prerendered.get(&template, namespace) {
+        Some(rendered) => Ok(rendered),
+        None => render_input(mold, &template, namespace, display_options.show_missing),
+    };
+    if let Ok(rendered) = &rendered {
+        if display_options.warn_missing {
+            warn_missing(&template, namespace, &rendered.missing);
+        }
+    }
+    match rendered {
+        Ok(Rendered {
+            output: rendered,
+            missing,
+        }) => match output_path.filter(|path| !is_stdio(path)) {
+            Some(output_path) => {
+                let output_path = expand_path(output_path);
+                let rendered = if write_options.header {
+                    let leader = write_options
+                        .header_comment
+                        .unwrap_or_else(|| header::comment_leader(&output_path));
+                    header::with_header(&rendered, leader, &template, namespace)
+                } else {
+                    rendered
+                };
+                let rendered = write_options.line_endings.apply(&rendered);
+                let diff_stat = display_options.show_diff.then(|| {
+                    display_diff(
+                        mold,
+                        &template,
+                        &output_path,
+                        namespace,
+                        &rendered,
+                        display_options.diff,
+                    )
+                });
+                let mut result = RenderResult::new(&template, Some(&output_path), Action::Create);
+                result.diff_stat = diff_stat.filter(|stat| !stat.is_empty());
+                result.hash = Some(state::hash(rendered.as_bytes()));
+                result.namespace = namespace.map(str::to_string);
+                result.missing = missing;
+                if let Ok(current) = std::fs::read_to_string(&output_path) {
+                    if current == rendered {
+                        result.action = Action::Unchanged;
+                    } else {
+                        result.action = Action::Update;
+                        result.lines_changed = Some(count_changed_lines(&current, &rendered));
+                    }
+                }
+                if !result.changed() {
+                    info!("`{}` is up to date", output_path.display());
+                }
+                if result.changed() {
+                    if let Some(action) = overwrite_conflict(state, &output_path, write_options) {
+                        info!("skipped writing `{}`", output_path.display());
+                        result.action = action;
+                        return result;
+                    }
+                }
+                if write_options.dry_run {
+                    result.content = Some(rendered);
+                    return result;
+                }
+                save_output(
+                    &template,
+                    &output_path,
+                    rendered.as_bytes(),
+                    write_options.permissions,
+                    display_options,
+                    write_options,
+                    state,
+                    &mut result,
+                );
+                result
+            }
+            None => {
+                let len = template.to_string_lossy().len() + 6;
+                let line = "-".repeat(len);
+                // with an explicit `-` output only the rendered content goes to stdout
+                let explicit_stdout = output_path.is_some();
+                let print_header = |header: String| {
+                    if explicit_stdout {
+                        eprintln!("{}", header);
+                    } else {
+                        println!("{}", header);
+                    }
+                };
+                if display_options.show_separator && !display_options.quiet {
+                    print_header(format!("{:=^1$}", "=", 80));
+                }
+                if display_options.show_headers && !display_options.quiet {
+                    print_header(format!("File: {}\n{}", template.display(), line));
+                }
+                if explicit_stdout {
+                    // output the content as is, just like it would be saved to a file
+                    print!("{}", rendered);
+                } else {
+                    println!("{}", rendered);
+                }
+                RenderResult {
+                    namespace: namespace.map(str::to_string),
+                    missing,
+                    ..RenderResult::new(&template, None, Action::Print)
+                }
+            }
+        },
+        Err(e) => {
+            eprintln!("{:?}", e);
+            RenderResult::new(&template, None, Action::Fail)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+enum PlanFormat {
+    Text,
+    Json,
+}
+
+/// Prints what a dry run would change.
+fn print_plan(results: &[RenderResult], format: PlanFormat) {
+    let results: Vec<_> = results
+        .iter()
+        .filter(|result| result.action != Action::Print)
+        .collect();
+    match format {
+        PlanFormat::Json => {
+            match serde_json::to_string_pretty(&results) {
+                Ok(json) => println!("{}", json),
+                Err(e) => exit!("failed to serialize the plan - {:?}", e),
+            }
+            return;
+        }
+        PlanFormat::Text if results.is_empty() => return,
+        PlanFormat::Text => {}
+    }
+    let mut counts = std::collections::BTreeMap::new();
+    for result in &results {
+        *counts.entry(result.action).or_insert(0) += 1;
+        let target = result
+            .output
+            .as_ref()
+            .map(|output| format!(" -> {}", output.display()))
+            .unwrap_or_default();
+        let lines = result
+            .lines_changed
+            .map(|lines| format!(" ({} lines changed)", lines))
+            .unwrap_or_default();
+        let link = result
+            .link
+            .as_ref()
+            .map(|link| format!(" (linked from {})", link.display()))
+            .unwrap_or_default();
+        println!(
+            "{:<13} {}{}{}{}",
+            result.action.describe(),
+            result.template.display(),
+            target,
+            lines,
+            link
+        );
+    }
+    let summary = counts
+        .iter()
+        .map(|(action, count)| match action {
+            Action::Create => format!("{} to create", count),
+            Action::Update => format!("{} to update", count),
+            Action::Skip => format!("{} to skip", count),
+            Action::Conflict => format!("{} to confirm", count),
+            action => format!("{} {}", count, action.describe()),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!("{}", summary);
+}
+
+/// Rules used to derive the name of the output file from the template name.
+struct OutputNameRules {
+    strip_suffix: Option<String>,
+    extension: Option<String>,
+}
+
+impl OutputNameRules {
+    fn apply(&self, template_name: &str) -> PathBuf {
+        let name = self
+            .strip_suffix
+            .as_deref()
+            .and_then(|suffix| template_name.strip_suffix(suffix))
+            .filter(|name| !name.is_empty())
+            .unwrap_or(template_name);
+        let mut name = PathBuf::from(name);
+        if let Some(extension) = &self.extension {
+            name.set_extension(extension.trim_start_matches('.'));
+        }
+        name
+    }
+}
+
+/// Returns the location of the rendered template. If `output_path` is a directory the output is
+/// placed in it with a name derived from the template name.
+fn output_for_template(
+    template: &Path,
+    output_path: &Path,
+    rules: &OutputNameRules,
+) -> anyhow::Result<PathBuf> {
+    let output_path = expand_path(output_path);
+    if !output_path.is_dir() {
+        return Ok(output_path);
+    }
+    let name = template
+        .file_name()
+        .filter(|_| !is_stdio(template))
+        .context("can't derive the output file name from the template")?;
+    Ok(output_path.join(rules.apply(&name.to_string_lossy())))
+}
+
+const CONTEXT_ENV: &str = "MOLD_CONTEXT";
+const CONTEXT_FILE_NAME: &str = "mold/context.yaml";
+
+/// Returns the locations where the context file is looked up when it's not specified explicitly.
+fn default_context_files() -> Vec<PathBuf> {
+    let paths = context_file_locations(
+        std::env::var_os(CONTEXT_ENV),
+        std::env::var_os("XDG_CONFIG_HOME"),
+        dirs::home_dir(),
+    );
+    // `%APPDATA%` is where configuration lives on Windows
+    #[cfg(windows)]
+    let paths = {
+        let mut paths = paths;
+        paths.extend(dirs::config_dir().map(|dir| dir.join(CONTEXT_FILE_NAME)));
+        paths
+    };
+    paths
+}
+
+/// Returns the context file locations derived from the values of `$MOLD_CONTEXT`,
+/// `$XDG_CONFIG_HOME` and the home directory in the order they are looked up.
+fn context_file_locations(
+    mold_context: Option<std::ffi::OsString>,
+    config_home: Option<std::ffi::OsString>,
+    home: Option<PathBuf>,
+) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    if let Some(path) = mold_context {
+        paths.push(expand_path(Path::new(&path)));
+    }
+    if let Some(config_home) = config_home {
+        paths.push(PathBuf::from(config_home).join(CONTEXT_FILE_NAME));
+    }
+    if let Some(home) = home {
+        paths.push(home.join(".config").join(CONTEXT_FILE_NAME));
+    }
+    paths
+}
+
+fn find_context_file(context_file: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    if let Some(context_file) = context_file {
+        return Ok(expand_path(&context_file));
+    }
+
+    first_context_file(default_context_files())
+}
+
+/// Returns the first of the locations `paths` where a context file exists.
+fn first_context_file(paths: Vec<PathBuf>) -> anyhow::Result<PathBuf> {
+    if let Some(path) = paths.iter().find(|path| path.is_file()) {
+        return Ok(path.clone());
+    }
+
+    let tried = paths
+        .iter()
+        .map(|path| format!("  {}", path.display()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Err(anyhow::anyhow!(
+        "no context file specified and none found, tried:\n{}",
+        tried
+    ))
+}
+
+fn context_file_path(context_file: Option<PathBuf>) -> PathBuf {
+    match find_context_file(context_file) {
+        Ok(context_file) => context_file,
+        Err(e) => exit!("failed to initialize mold - {}", e),
+    }
+}
+
+/// Placeholder in the output pattern of `render-all` replaced with the name of the namespace.
+const NAMESPACE_PLACEHOLDER: &str = "{namespace}";
+
+fn parse_variable_override(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("invalid variable `{}`, expected `key=value`", s)),
+    }
+}
+
+/// Options from the command line applied to every loaded context.
+struct LoadOptions {
+    overrides: Vec<(String, String)>,
+    include_dirs: Vec<PathBuf>,
+    no_builtins: bool,
+    no_nested_render: bool,
+    allow_commands: bool,
+    ignore_namespace_case: bool,
+    template_root: Option<PathBuf>,
+    missing_placeholder: Option<String>,
+    /// Fail on context files of an unsupported version instead of warning.
+    strict: bool,
+}
+
+/// Returns the expanded paths of `context_files`, or the context file found in the default
+/// locations if none were specified.
+fn context_file_paths(context_files: Vec<PathBuf>) -> Vec<PathBuf> {
+    if context_files.is_empty() {
+        return vec![context_file_path(None)];
+    }
+    context_files.iter().map(|file| expand_path(file)).collect()
+}
+
+/// Loads the context files merged left to right, later files override the earlier ones.
+fn load_mold(context_files: &[PathBuf], options: &LoadOptions) -> Mold {
+    let mut mold =
+        match Mold::new_merged(context_files, options.strict, options.ignore_namespace_case) {
+            Ok(mold) => mold,
+            Err(e) => exit!("failed to initialize mold - {:?}", e),
+        };
+    for dir in &options.include_dirs {
+        mold.add_include_dir(expand_path(dir));
+    }
+    if options.no_builtins {
+        mold.set_builtins_enabled(false);
+    }
+    if options.no_nested_render {
+        mold.set_nested_render(false);
+    }
+    mold.allow_commands(options.allow_commands);
+    if let Some(root) = &options.template_root {
+        // absolute so that resolving an already resolved template doesn't join the root twice
+        let root = expand_path(root);
+        mold.set_template_root(std::path::absolute(&root).unwrap_or(root));
+    }
+    mold.set_missing_placeholder(options.missing_placeholder.clone());
+    let context = mold.context_mut();
+    for (key, value) in &options.overrides {
+        // variable names can contain dots so the prefix only selects an existing namespace
+        match key
+            .split_once('.')
+            .filter(|(namespace, _)| context.has_namespace(namespace))
+        {
+            Some((namespace, key)) => context.set_variable(Some(namespace), key, value),
+            None => context.set_variable(None, key, value),
+        }
+    }
+    mold
+}
+
+/// Initializes logging to stderr. Without `-v` or `-q` the level is taken from `MOLD_LOG`, then
+/// `RUST_LOG`, and defaults to warnings only. `-q` limits the logs to errors, `-v` adds the
+/// unchanged and skipped files and timings, `-vv` debug and `-vvv` trace logs.
+fn init_logger(verbose: usize, quiet: bool) {
+    let env = env_logger::Env::default().filter_or(
+        "MOLD_LOG",
+        std::env::var("RUST_LOG").unwrap_or_else(|_| "warn".into()),
+    );
+    let mut builder = env_logger::Builder::from_env(env);
+    let level = match (quiet, verbose) {
+        (true, _) => Some(log::LevelFilter::Error),
+        (false, 0) => None,
+        (false, 1) => Some(log::LevelFilter::Info),
+        (false, 2) => Some(log::LevelFilter::Debug),
+        (false, _) => Some(log::LevelFilter::Trace),
+    };
+    if let Some(level) = level {
+        builder.filter_level(level);
+    }
+    builder.init();
+}
+
+fn state_file_path(state_file: Option<PathBuf>) -> Option<PathBuf> {
+    state_file
+        .map(|path| expand_path(&path))
+        .or_else(state::default_state_file)
+}
+
+/// Returns a command running `command` with `$SHELL -c`, or `sh -c` if `SHELL` is not set. The
+/// same shell runs the hooks and the `--exec` command.
+fn shell_command(command: &str) -> std::process::Command {
+    let shell = std::env::var_os("SHELL")
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| "sh".into());
+    let mut shell = std::process::Command::new(shell);
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// Runs `command` with the shell printing its output prefixed with `name`. Failures are only
+/// reported so that a failing hook doesn't abort the remaining renders.
+fn run_hook(name: &str, command: &str) {
+    let output = match shell_command(command).output() {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("[{}] failed to run hook `{}` - {:?}", name, command, e);
+            return;
+        }
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        println!("[{}] {}", name, line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        eprintln!("[{}] {}", name, line);
+    }
+    if !output.status.success() {
+        eprintln!("[{}] hook `{}` failed - {}", name, command, output.status);
+    }
+}
+
+/// Runs the hook if hooks are enabled, otherwise only reports that it was skipped.
+fn trigger_hook(
+    name: &str,
+    command: &str,
+    display_options: &DisplayOptions,
+    write_options: &WriteOptions<'_>,
+) {
+    if !write_options.run_hooks {
+        if !display_options.quiet {
+            println!(
+                "[{}] skipping hook `{}`, use --run-hooks to run it",
+                name, command
+            );
+        }
+        return;
+    }
+    if !display_options.quiet {
+        println!("[{}] running `{}`", name, command);
+    }
+    run_hook(name, command);
+}
+
+/// Directory next to the context file where templates of entries in symlink mode are rendered.
+const RENDERED_DIR: &str = ".mold-rendered";
+
+fn rendered_dir(context_file: &Path) -> PathBuf {
+    let context_file = context_file
+        .canonicalize()
+        .unwrap_or_else(|_| context_file.to_path_buf());
+    context_file
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(RENDERED_DIR)
+}
+
+/// Returns the location of the rendered file that `output` links to. The structure of the
+/// output path is mirrored inside of the rendered directory.
+fn rendered_path(rendered_dir: &Path, output: &Path) -> PathBuf {
+    rendered_dir.join(
+        output
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect::<PathBuf>(),
+    )
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+fn is_linked(link: &Path, target: &Path) -> bool {
+    std::fs::read_link(link)
+        .map(|path| path == target)
+        .unwrap_or(false)
+}
+
+/// Makes `link` a symlink to `target`. Files other than symlinks are only replaced with `--force`.
+fn link_output(
+    target: &Path,
+    link: &Path,
+    display_options: &DisplayOptions,
+    write_options: &WriteOptions<'_>,
+    result: &mut RenderResult,
+) {
+    result.link = Some(link.to_path_buf());
+    if is_linked(link, target) {
+        debug!(
+            "`{}` already links to `{}`",
+            link.display(),
+            target.display()
+        );
+        return;
+    }
+    let existing = std::fs::symlink_metadata(link).ok();
+    if let Some(metadata) = &existing {
+        if !metadata.file_type().is_symlink() && !write_options.force {
+            warn!(
+                "skipping `{}` as it is not a symlink, use --force to replace it",
+                link.display()
+            );
+            result.action = Action::Skip;
+            return;
+        }
+    }
+    if result.action == Action::Unchanged {
+        result.action = Action::Update;
+    }
+    if write_options.dry_run {
+        return;
+    }
+    if !display_options.quiet {
+        println!("linking {} to {}", link.display(), target.display());
+    }
+    let linked = match existing {
+        Some(_) => std::fs::remove_file(link).and_then(|_| symlink(target, link)),
+        None => symlink(target, link),
+    };
+    if let Err(e) = linked {
+        eprintln!(
+            "failed to link `{}` to `{}` - {:?}",
+            link.display(),
+            target.display(),
+            e
+        );
+        result.action = Action::Fail;
+    }
+}
+
+/// Renders all entries of the context running their hooks unless in dry run mode.
+#[allow(clippy::too_many_arguments)]
+fn render_context(
+    mold: &Mold,
+    namespace: Option<&str>,
+    rendered_dir: &Path,
+    entry_filter: &EntryFilter,
+    jobs: Option<usize>,
+    display_options: &DisplayOptions,
+    write_options: &WriteOptions<'_>,
+    state: &mut StateFile,
+) -> Vec<RenderResult> {
+    let prerendered = prerender(
+        mold,
+        mold.render_entries()
+            .filter(|(template, entry)| {
+                !entry.verbatim() && entry_filter.selects(&template_path(mold, template))
+            })
+            .map(|(template, entry)| (template, entry.namespace().or(namespace))),
+        jobs,
+        display_options.show_missing,
+    );
+    let mut results = vec![];
+    for (template, entry) in mold.render_entries() {
+        let template = template.as_path();
+        if !entry_filter.selects(&template_path(mold, template)) {
+            info!("skipping `{}` excluded by the filter", template.display());
+            continue;
+        }
+        let namespace = entry.namespace().or(namespace);
+        let write_options = &WriteOptions {
+            permissions: entry.permissions().or(write_options.permissions),
+            header: entry.header() || write_options.header,
+            header_comment: entry.header_comment(),
+            privileged: entry.privileged() || write_options.privileged,
+            verbatim: entry.verbatim(),
+            line_endings: write_options.line_endings.of_entry(entry),
+            ..*write_options
+        };
+        if write_options.skip_binary && !entry.verbatim() {
+            let template = template_path(mold, template);
+            if let Ok(false) = mold::is_text_file(&template) {
+                warn!("skipping `{}` as it is not UTF-8 text", template.display());
+                results.push(RenderResult::new(
+                    &template,
+                    Some(entry.output()),
+                    Action::Skip,
+                ));
+                continue;
+            }
+        }
+        let result = if write_options.symlink || entry.mode() == RenderMode::Symlink {
+            // the rendered file is owned by the user, only the outputs are privileged
+            let write_options = &WriteOptions {
+                privileged: false,
+                ..*write_options
+            };
+            let output = entry.output();
+            let target = rendered_path(rendered_dir, output);
+            if let Some(parent) = target.parent().filter(|_| !write_options.dry_run) {
+                if let Err(e) = std::fs::create_dir_all(parent) {
+                    eprintln!("failed to create `{}` - {:?}", parent.display(), e);
+                }
+            }
+            let mut result = render_template(
+                mold,
+                namespace,
+                template,
+                Some(&target),
+                &prerendered,
+                display_options,
+                write_options,
+                state,
+            );
+            if matches!(
+                result.action,
+                Action::Create | Action::Update | Action::Unchanged
+            ) {
+                link_output(&target, output, display_options, write_options, &mut result);
+            }
+            result
+        } else {
+            render_template(
+                mold,
+                namespace,
+                template,
+                Some(entry.output()),
+                &prerendered,
+                display_options,
+                write_options,
+                state,
+            )
+        };
+        if result.changed() && !write_options.dry_run {
+            if let Some(command) = entry.on_change() {
+                trigger_hook(
+                    &template.to_string_lossy(),
+                    command,
+                    display_options,
+                    write_options,
+                );
+            }
+        }
+        let failed = result.action == Action::Fail;
+        results.push(result);
+        if failed && write_options.fail_fast {
+            break;
+        }
+    }
+
+    if results.iter().any(RenderResult::changed) && !write_options.dry_run {
+        if let Some(command) = mold.context().hooks().post_render_all() {
+            trigger_hook("post_render_all", command, display_options, write_options);
+        }
+    }
+    results
+}
+
+/// Runs the `--exec` command with `$SHELL` once all renders succeeded and any output changed.
+/// Exits with the exit code of the command if it fails.
+fn exec_after_render(command: &str, results: &[RenderResult], dry_run: bool) {
+    let failed = results.iter().any(|result| result.action == Action::Fail);
+    if failed || !results.iter().any(RenderResult::changed) {
+        return;
+    }
+    if dry_run {
+        eprintln!("would run `{}`", command);
+        return;
+    }
+    match shell_command(command).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            eprintln!("command `{}` failed - {}", command, status);
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        Err(e) => exit!("failed to run command `{}` - {:?}", command, e),
+    }
+}
+
+/// Returns the namespace recorded by the last `apply` if it was done with the same context file.
+fn applied_namespace(state: &StateFile, context_file: &Path) -> Option<String> {
+    let applied = state.state().applied.as_ref()?;
+    if !applied.is_for(context_file) {
+        debug!("last apply was done with a different context file");
+        return None;
+    }
+    applied.namespace.clone()
+}
+
+/// Returns `namespace` or the `default_namespace` of the context if it's not specified.
+fn namespace_or_default(mold: &Mold, namespace: Option<String>) -> Option<String> {
+    namespace.or_else(|| {
+        let namespace = mold.context().default_namespace()?;
+        debug!("using the default namespace `{}` of the context", namespace);
+        Some(namespace.to_string())
+    })
+}
+
+/// Exits if `namespace` doesn't exist in the context so that a typo in its name doesn't silently
+/// fall back to the global variables.
+fn check_namespace(mold: &Mold, namespace: Option<&str>) {
+    match namespace {
+        Some(namespace) if !mold.context().has_namespace(namespace) => {
+            let available = mold.context().namespace_names();
+            if available.is_empty() {
+                exit!(
+                    "namespace `{}` not found, the context has no namespaces",
+                    namespace
+                );
+            }
+            exit!(
+                "namespace `{}` not found, available namespaces: {}",
+                namespace,
+                available.join(", ")
+            );
+        }
+        Some(namespace) => debug!("selected namespace `{}`", namespace),
+        None => debug!("no namespace selected, using only global variables"),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum OutputStatus {
+    UpToDate,
+    Missing,
+    /// The output wasn't edited since mold wrote it so the template or context changed.
+    Outdated,
+    /// The output was edited outside of mold.
+    Modified,
+    /// The output differs and there is no record of mold writing it.
+    Differs,
+    /// The output of an entry in symlink mode is not a symlink to the rendered file.
+    Unlinked,
+    Error,
+}
+
+impl OutputStatus {
+    fn describe(&self) -> &'static str {
+        match self {
+            OutputStatus::UpToDate => "up to date",
+            OutputStatus::Missing => "missing",
+            OutputStatus::Outdated => "outdated",
+            OutputStatus::Modified => "modified",
+            OutputStatus::Differs => "differs",
+            OutputStatus::Unlinked => "not linked",
+            OutputStatus::Error => "error",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            OutputStatus::UpToDate => Color::Green,
+            OutputStatus::Outdated | OutputStatus::Differs | OutputStatus::Unlinked => {
+                Color::Yellow
+            }
+            OutputStatus::Missing | OutputStatus::Modified | OutputStatus::Error => Color::Red,
+        }
+    }
+}
+
+fn output_status(state: &StateFile, output_path: &Path, rendered: &[u8]) -> OutputStatus {
+    let current = match std::fs::read(output_path) {
+        Ok(current) => current,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return OutputStatus::Missing,
+        Err(_) => return OutputStatus::Error,
+    };
+    if current == rendered {
+        return OutputStatus::UpToDate;
+    }
+    match state.state().output_hash(output_path) {
+        Some(hash) if hash == state::hash(&current) => OutputStatus::Outdated,
+        Some(_) => OutputStatus::Modified,
+        None => OutputStatus::Differs,
+    }
+}
+
+/// Writes imported variables as the namespace `name` to the context file, or prints the namespace
+/// if `stdout` is set.
+fn import_namespace(
+    context_file: Option<PathBuf>,
+    name: &str,
+    variables: &IndexMap<String, String>,
+    existing: import::Existing,
+    stdout: bool,
+) {
+    if stdout {
+        match serde_yaml::to_string(&import::namespace_value(name, variables)) {
+            Ok(yaml) => print!("{}", yaml),
+            Err(e) => exit!("failed to serialize the namespace - {:?}", e),
+        }
+        return;
+    }
+    let context_file = context_file_path(context_file);
+    if let Err(e) = import::write_namespace(&context_file, name, variables, existing) {
+        exit!(
+            "failed to write namespace `{}` to `{}` - {:?}",
+            name,
+            context_file.display(),
+            e
+        );
+    }
+    println!(
+        "imported {} variables to namespace `{}` in {}",
+        variables.len(),
+        name,
+        context_file.display()
+    );
+}
+
+/// Exits with a non-zero code if any template failed to render, the number of rendered and failed
+/// templates is printed before.
+fn exit_on_failure(results: &[RenderResult]) {
+    let failed = results
+        .iter()
+        .filter(|result| result.action == Action::Fail)
+        .count();
+    if failed > 0 {
+        eprintln!("{} rendered, {} failed", results.len() - failed, failed);
+        std::process::exit(1);
+    }
+}
+
+/// Exits with a non-zero code if any variable had no value when rendering the templates, the
+/// missing variables are listed for every template.
+fn exit_on_missing(results: &[RenderResult]) {
+    let mut missing = false;
+    for result in results.iter().filter(|result| !result.missing.is_empty()) {
+        eprintln!(
+            "missing variables in `{}`: {}",
+            result.template.display(),
+            result.missing.join(", ")
+        );
+        missing = true;
+    }
+    if missing {
+        std::process::exit(1);
+    }
+}
+
+/// Saves the changed outputs of a dry run as a patch and/or a script. The outputs are sorted by
+/// path so that the same changes always result in the same files.
+fn emit_changes(results: &[RenderResult], patch: Option<&Path>, script: Option<&Path>) {
+    if patch.is_none() && script.is_none() {
+        return;
+    }
+    let mut changes: Vec<_> = results
+        .iter()
+        .filter(|result| result.changed())
+        .filter_map(|result| {
+            let output = result.output.as_ref()?;
+            Some(emit::Change {
+                path: std::path::absolute(output).unwrap_or_else(|_| output.clone()),
+                current: std::fs::read_to_string(output).ok(),
+                content: result.content.as_deref()?,
+                link: result.link.clone(),
+            })
+        })
+        .collect();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    for (path, content) in [
+        (patch, emit::patch as fn(&[emit::Change<'_>]) -> String),
+        (script, emit::script),
+    ] {
+        if let Some(path) = path {
+            let path = expand_path(path);
+            if let Err(e) = std::fs::write(&path, content(&changes)) {
+                exit!("failed to save `{}` - {:?}", path.display(), e);
+            }
+            println!(
+                "saved {} changed outputs to {}",
+                changes.len(),
+                path.display()
+            );
+        }
+    }
+}
+
+/// Writes the hashes of all rendered outputs to the manifest at `path`.
+fn write_manifest(path: &Path, results: &[RenderResult]) {
+    let mut manifest = Manifest::default();
+    for result in results {
+        if let (Some(output), Some(hash)) = (&result.output, &result.hash) {
+            manifest.record(output, hash.clone());
+        }
+    }
+    if let Err(e) = manifest.save(&expand_path(path)) {
+        eprintln!("failed to save manifest `{}` - {:?}", path.display(), e);
+    }
+}
+
+/// A variable of the global namespace that is defined in other namespaces as well.
+#[derive(Serialize)]
+struct Shadowed<'a> {
+    key: &'a str,
+    global: String,
+    /// Values of the namespaces, each wins over the global value when rendering with it.
+    namespaces: Vec<ShadowingValue<'a>>,
+}
+
+#[derive(Serialize)]
+struct ShadowingValue<'a> {
+    namespace: &'a str,
+    value: String,
+    /// The namespace defines the same value as the global namespace.
+    same_as_global: bool,
+}
+
+/// Returns the variables of the global namespace defined in other namespaces as well sorted by
+/// name, the values of secret variables are redacted.
+fn shadowed_variables(mold: &Mold) -> Vec<Shadowed<'_>> {
+    let context = mold.context();
+    let global = match context.namespace(mold::GLOBAL_NS) {
+        Some(global) => global,
+        None => return vec![],
+    };
+    let display = |value: &str, secret: bool| match secret {
+        true => mold::REDACTED.to_string(),
+        false => value.to_string(),
+    };
+    global
+        .iter()
+        .filter_map(|(key, global_value)| {
+            let global_secret = global.secrets().contains(key);
+            let namespaces: Vec<_> = context
+                .namespaces()
+                .filter_map(|namespace| {
+                    let value = namespace.get(key)?;
+                    Some(ShadowingValue {
+                        namespace: namespace.name(),
+                        value: display(value, global_secret || namespace.secrets().contains(key)),
+                        same_as_global: value == global_value,
+                    })
+                })
+                .collect();
+            (!namespaces.is_empty()).then(|| Shadowed {
+                key,
+                global: display(global_value, global_secret),
+                namespaces,
+            })
+        })
+        .collect()
+}
+
+fn print_shadowed(shadowed: &[Shadowed<'_>]) {
+    for variable in shadowed {
+        println!("{}", variable.key.bold());
+        let namespace_width = variable
+            .namespaces
+            .iter()
+            .map(|shadowing| shadowing.namespace.len())
+            .max()
+            .unwrap_or_default();
+        let value_width = variable
+            .namespaces
+            .iter()
+            .map(|shadowing| format!("{:?}", shadowing.value).len())
+            .max()
+            .unwrap_or_default();
+        for shadowing in &variable.namespaces {
+            let same = if shadowing.same_as_global {
+                ", same value"
+            } else {
+                ""
+            };
+            println!(
+                "  {:<namespace_width$}  {}  {}",
+                shadowing.namespace,
+                format!(
+                    "{:<width$}",
+                    format!("{:?}", shadowing.value),
+                    width = value_width
+                )
+                .green(),
+                format!("(GLOBAL: {:?}{})", variable.global, same).dimmed(),
+                namespace_width = namespace_width
+            );
+        }
+    }
+    match shadowed.len() {
+        0 => println!("no variables of GLOBAL are shadowed by a namespace"),
+        1 => println!("1 variable of GLOBAL is shadowed by a namespace"),
+        n => println!("{} variables of GLOBAL are shadowed by namespaces", n),
+    }
+}
+
+/// Prints the resolution of a variable with its nested variables indented below it.
+fn print_explanation(explanation: &VariableExplanation, namespace: Option<&str>, depth: usize) {
+    let indent = depth * 2;
+    let print = |label: &str, value: Option<&str>| {
+        let value = match value {
+            Some(value) => format!("{:?}", value),
+            None => "not defined".dimmed().to_string(),
+        };
+        println!("{:indent$}  {:<10} {}", "", label, value, indent = indent);
+    };
+    println!("{:indent$}{}", "", explanation.name.bold(), indent = indent);
+    if explanation.cycle {
+        println!(
+            "{:indent$}  {}",
+            "",
+            "references itself, not expanded".red(),
+            indent = indent
+        );
+        return;
+    }
+    if let Some(namespace) = namespace.filter(|namespace| *namespace != mold::GLOBAL_NS) {
+        print(
+            &format!("{}:", namespace),
+            explanation.namespace_value.as_deref(),
+        );
+    }
+    print("GLOBAL:", explanation.global_value.as_deref());
+    if let Some(builtin) = &explanation.builtin {
+        print("builtin:", Some(builtin));
+    }
+    if !explanation.nested.is_empty() {
+        println!("{:indent$}  references:", "", indent = indent);
+        for nested in &explanation.nested {
+            print_explanation(nested, namespace, depth + 2);
+        }
+    }
+    match &explanation.value {
+        Some(value) => println!(
+            "{:indent$}  {:<10} {}",
+            "",
+            "value:",
+            format!("{:?}", value).green(),
+            indent = indent
+        ),
+        None if explanation.has_cycle() => println!(
+            "{:indent$}  {}",
+            "",
+            "not expanded, a referenced variable references itself".red(),
+            indent = indent
+        ),
+        None if explanation.similar.is_empty() => {
+            println!("{:indent$}  {}", "", "missing".yellow(), indent = indent)
+        }
+        None => println!(
+            "{:indent$}  {}, similar: {}",
+            "",
+            "missing".yellow(),
+            explanation.similar.join(", "),
+            indent = indent
+        ),
+    }
+}
+
+/// Version of the report written with `--summary-json`, increased when the format changes in a
+/// way that isn't backwards compatible.
+const SUMMARY_VERSION: u32 = 1;
+
+/// What happened to a template, as reported by `--summary-json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    /// The output was created or updated, in dry run mode it would have been.
+    Written,
+    Unchanged,
+    Skipped,
+    Failed,
+    /// The rendered content was printed instead of being saved.
+    Printed,
+}
+
+impl From<Action> for Outcome {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Create | Action::Update => Outcome::Written,
+            Action::Unchanged => Outcome::Unchanged,
+            Action::Skip | Action::Conflict => Outcome::Skipped,
+            Action::Fail => Outcome::Failed,
+            Action::Print => Outcome::Printed,
+        }
+    }
+}
+
+/// Report of a run written with `--summary-json`. Every field is always present so that
+/// consumers don't have to handle missing ones.
+#[derive(Serialize)]
+struct Summary<'a> {
+    version: u32,
+    dry_run: bool,
+    templates: Vec<SummaryTemplate<'a>>,
+    counts: SummaryCounts,
+}
+
+#[derive(Serialize)]
+struct SummaryTemplate<'a> {
+    template: &'a Path,
+    output: Option<&'a Path>,
+    namespace: Option<&'a str>,
+    outcome: Outcome,
+    missing: &'a [String],
+}
+
+#[derive(Default, Serialize)]
+struct SummaryCounts {
+    written: usize,
+    unchanged: usize,
+    skipped: usize,
+    failed: usize,
+    printed: usize,
+}
+
+/// Writes the report of a run with the outcome of every template in `results` to `path`.
+fn write_summary(path: &Path, results: &[RenderResult], dry_run: bool) {
+    let mut counts = SummaryCounts::default();
+    let templates = results
+        .iter()
+        .map(|result| {
+            let outcome = Outcome::from(result.action);
+            *match outcome {
+                Outcome::Written => &mut counts.written,
+                Outcome::Unchanged => &mut counts.unchanged,
+                Outcome::Skipped => &mut counts.skipped,
+                Outcome::Failed => &mut counts.failed,
+                Outcome::Printed => &mut counts.printed,
+            } += 1;
+            SummaryTemplate {
+                template: &result.template,
+                output: result.output.as_deref(),
+                namespace: result.namespace.as_deref(),
+                outcome,
+                missing: &result.missing,
+            }
+        })
+        .collect();
+    let summary = Summary {
+        version: SUMMARY_VERSION,
+        dry_run,
+        templates,
+        counts,
+    };
+    let written = serde_json::to_string_pretty(&summary)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| Ok(std::fs::write(expand_path(path), json + "\n")?));
+    if let Err(e) = written {
+        eprintln!("failed to save summary `{}` - {:?}", path.display(), e);
+    }
+}
+
+/// Longest text of a text token displayed by `tokens`, longer text is truncated.
+const TOKEN_TEXT_LEN: usize = 40;
+
+/// Prints `tokens` with their locations, the bodies of conditional blocks are indented by
+/// `depth`. The number of tokens of every kind is added to `counts`.
+fn print_tokens(
+    tokens: &[TemplateToken],
+    depth: usize,
+    counts: &mut std::collections::BTreeMap<&'static str, usize>,
+) {
+    for token in tokens {
+        let (kind, description) = match &token.kind {
+            TokenKind::Text { text } => {
+                let truncated: String = text.chars().take(TOKEN_TEXT_LEN).collect();
+                let ellipsis = if truncated.len() < text.len() {
+                    "..."
+                } else {
+                    ""
+                };
+                ("text", format!("Text({:?}{})", truncated, ellipsis))
+            }
+            TokenKind::Variable { name, filters, raw } if filters.is_empty() => (
+                "variable",
+                format!("Variable {{ name: {:?}, raw: {:?} }}", name, raw),
+            ),
+            TokenKind::Variable { name, filters, raw } => (
+                "variable",
+                format!(
+                    "Variable {{ name: {:?}, filters: {:?}, raw: {:?} }}",
+                    name, filters, raw
+                ),
+            ),
+            TokenKind::FileSource { path, trim, .. } => (
+                "file source",
+                format!("FileSource {{ path: {:?}, trim: {} }}", path, trim),
+            ),
+            TokenKind::Require { names } => ("require", format!("Require({:?})", names)),
+            TokenKind::IfExists { path, .. } => {
+                ("if_exists", format!("IfExists {{ path: {:?} }}", path))
+            }
+            TokenKind::If { name, negated, .. } => (
+                "if",
+                format!("If {{ name: {:?}, negated: {} }}", name, negated),
+            ),
+            TokenKind::Command { command } => ("command", format!("Command({:?})", command)),
+        };
+        *counts.entry(kind).or_insert(0) += 1;
+        println!(
+            "{:<8} {:indent$}{}",
+            format!("{}:{}", token.line, token.column),
+            "",
+            description,
+            indent = depth * 2
+        );
+        match &token.kind {
+            TokenKind::IfExists { body, .. } => print_tokens(body, depth + 1, counts),
+            TokenKind::If {
+                body, else_body, ..
+            } => {
+                print_tokens(body, depth + 1, counts);
+                if !else_body.is_empty() {
+                    println!("{:<8} {:indent$}Else", "", "", indent = depth * 2);
+                    print_tokens(else_body, depth + 1, counts);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Prints every template of the context with its output resolved to absolute paths, templates
+/// that aren't text are flagged unless the entry is verbatim. Returns `true` if all templates
+/// exist.
+fn list_renders(mold: &Mold, entry_filter: &EntryFilter) -> bool {
+    let absolute = |path: PathBuf| std::path::absolute(&path).unwrap_or(path);
+    let mut all_exist = true;
+    for (template, entry) in mold.render_entries() {
+        let template = template_path(mold, &template);
+        if !entry_filter.selects(&template) {
+            continue;
+        }
+        let template = absolute(template);
+        let output = absolute(entry.output().to_path_buf());
+        let status = if !template.is_file() {
+            all_exist = false;
+            "missing".red()
+        } else if !entry.verbatim() && matches!(mold::is_text_file(&template), Ok(false)) {
+            "binary".yellow()
+        } else {
+            "ok".green()
+        };
+        let mut notes = vec![];
+        if let Some(namespace) = entry.namespace() {
+            notes.push(format!("namespace: {}", namespace));
+        }
+        if entry.mode() == RenderMode::Symlink {
+            notes.push("symlink".to_string());
+        }
+        if entry.verbatim() {
+            notes.push("verbatim".to_string());
+        }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", notes.join(", "))
+        };
+        println!(
+            "{:<8} {} -> {}{}",
+            status,
+            template.display(),
+            output.display(),
+            notes
+        );
+    }
+    all_exist
+}
+
+/// Prints the status of every render entry of the context, returns `true` if all outputs are
+/// up to date.
+fn print_status(
+    mold: &Mold,
+    namespace: Option<&str>,
+    rendered_dir: &Path,
+    show_missing: bool,
+    diff: Option<DiffOptions>,
+    header: bool,
+    state: &StateFile,
+) -> bool {
+    let mut counts = std::collections::BTreeMap::new();
+    let mut stats = vec![];
+    for (template, entry) in mold.render_entries() {
+        let namespace = entry.namespace().or(namespace);
+        let template = template_path(mold, &template);
+        let link = entry.output().to_path_buf();
+        let output_path = match entry.mode() {
+            RenderMode::Copy => link.clone(),
+            RenderMode::Symlink => rendered_path(rendered_dir, &link),
+        };
+        let rendered = if is_verbatim(&template, entry.verbatim()) {
+            std::fs::read(&template)
+                .with_context(|| format!("failed to read `{}`", template.display()))
+        } else {
+            mold.render_file(&template, namespace, show_missing)
+                .map(|rendered| {
+                    if !header && !entry.header() {
+                        return rendered;
+                    }
+                    let leader = entry
+                        .header_comment()
+                        .unwrap_or_else(|| header::comment_leader(&output_path));
+                    header::with_header(&rendered, leader, &template, namespace)
+                })
+                .map(|rendered| LineEndings::default().of_entry(entry).apply(&rendered))
+                .map(String::into_bytes)
+        };
+        let (status, rendered) = match rendered {
+            Ok(rendered) => match output_status(state, &output_path, &rendered) {
+                OutputStatus::UpToDate
+                    if entry.mode() == RenderMode::Symlink && !is_linked(&link, &output_path) =>
+                {
+                    (OutputStatus::Unlinked, Some(rendered))
+                }
+                status => (status, Some(rendered)),
+            },
+            Err(e) => {
+                eprintln!("{:?}", e);
+                (OutputStatus::Error, None)
+            }
+        };
+        *counts.entry(status).or_insert(0) += 1;
+        println!(
+            "{:<12} {} -> {}",
+            status.describe().color(status.color()),
+            template.display(),
+            link.display()
+        );
+        let rendered = rendered
+            .filter(|rendered| !mold::is_binary(rendered))
+            .and_then(|rendered| String::from_utf8(rendered).ok());
+        if let (Some(rendered), Some(diff)) = (rendered, diff) {
+            if matches!(
+                status,
+                OutputStatus::Outdated | OutputStatus::Modified | OutputStatus::Differs
+            ) {
+                stats.push(display_diff(
+                    mold,
+                    &template,
+                    &output_path,
+                    namespace,
+                    &rendered,
+                    diff,
+                ));
+            }
+        }
+    }
+    print_diff_total(stats);
+
+    let summary = counts
+        .iter()
+        .map(|(status, count)| format!("{} {}", count, status.describe()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!(
+        "{}",
+        if summary.is_empty() {
+            "nothing to render".into()
+        } else {
+            summary
+        }
+    );
+    counts
+        .keys()
+        .all(|status| *status == OutputStatus::UpToDate)
+}
+
+fn main() {
+    let opts = Opts::parse();
+    let quiet = opts.quiet;
+    init_logger(opts.verbose, quiet);
+    let mut state = StateFile::load(state_file_path(opts.state_file));
+    let load_options = LoadOptions {
+        overrides: opts.overrides,
+        include_dirs: opts.include_dirs,
+        no_builtins: opts.no_builtins,
+        no_nested_render: opts.no_nested_render,
+        allow_commands: opts.allow_commands,
+        ignore_namespace_case: opts.ignore_namespace_case,
+        template_root: opts.template_root,
+        missing_placeholder: opts.missing_placeholder,
+        strict: opts.strict,
+    };
+
+    match opts.command {
+        Subcommand::Render {
+            context,
+            templates,
+            output_path,
             namespace,
-            show_missing,
-            show_diff,
-            dry_run,
+            error_on_missing,
+            show_headers,
+            no_separator,
+            strip_suffix,
+            output_extension,
+            chmod,
+            from_meta,
+            emit_patch,
+            emit_script,
+            jobs,
+            options,
         } => {
-            let mold = match Mold::new(&context_file) {
-                Ok(mold) => mold,
-                Err(e) => exit!("failed to initialize mold - {:?}", e),
+            let RenderOptions {
+                show_missing,
+                show_diff,
+                diff_options,
+                line_endings,
+                dry_run,
+                format,
+                force,
+                interactive,
+                header,
+                summary_json,
+                fail_fast,
+            } = options;
+            let dry_run = dry_run || emit_patch.is_some() || emit_script.is_some();
+            if templates.len() > 1 && templates.iter().any(|t| is_stdio(t)) {
+                exit!("reading a template from standard input can't be mixed with other templates");
+            }
+            if from_meta && templates.iter().any(|t| is_stdio(t)) {
+                exit!("`--from-meta` can't be used with a template from standard input");
+            }
+            // every namespace is paired with the output path at the same position
+            let targets: Vec<(Option<String>, Option<PathBuf>)> =
+                match (namespace.len(), output_path.len()) {
+                    (namespaces, outputs) if namespaces <= 1 && outputs <= 1 => {
+                        vec![(namespace.into_iter().next(), output_path.into_iter().next())]
+                    }
+                    (namespaces, outputs) if namespaces == outputs => namespace
+                        .into_iter()
+                        .map(Some)
+                        .zip(output_path.into_iter().map(Some))
+                        .collect(),
+                    (namespaces, outputs) => exit!(
+                        "got {} namespaces and {} output paths, each `--namespace` has to be paired with an `--output-path`",
+                        namespaces,
+                        outputs
+                    ),
+                };
+            if targets.len() > 1 && templates.iter().any(|t| is_stdio(t)) {
+                exit!("a template from standard input can't be rendered to multiple outputs");
+            }
+            for output_path in targets.iter().filter_map(|(_, output)| output.as_deref()) {
+                let output_path = expand_path(output_path);
+                if templates.len() > 1 && !is_stdio(&output_path) && !output_path.is_dir() {
+                    exit!(
+                        "output path `{}` is not a directory, rendering multiple templates requires an existing directory",
+                        output_path.display()
+                    );
+                }
+            }
+            let mold = load_mold(&context.paths(), &load_options);
+            for (namespace, _) in &targets {
+                check_namespace(&mold, namespace.as_deref());
+            }
+            let display_opts = DisplayOptions {
+                show_missing,
+                warn_missing: !opts.no_warn_missing,
+                show_diff,
+                diff: diff_options,
+                show_headers,
+                show_separator: !no_separator,
+                quiet,
             };
+            let write_opts = WriteOptions {
+                dry_run,
+                force,
+                interactive,
+                symlink: false,
+                permissions: chmod,
+                header,
+                header_comment: None,
+                run_hooks: false,
+                privileged: opts.sudo,
+                elevate_command: &opts.elevate_command,
+                verbatim: false,
+                skip_binary: false,
+                line_endings,
+                fail_fast,
+            };
+
+            let name_rules = OutputNameRules {
+                strip_suffix,
+                extension: output_extension,
+            };
+
+            // all declarations are checked before anything is rendered
+            let templates: Vec<_> = templates
+                .into_iter()
+                .map(|template| {
+                    let meta = if from_meta {
+                        match TemplateMeta::from_file(&template_path(&mold, &template)) {
+                            Ok(meta) => meta,
+                            Err(e) => {
+                                exit!("failed to read meta of `{}` - {:?}", template.display(), e)
+                            }
+                        }
+                    } else {
+                        TemplateMeta::default()
+                    };
+                    if targets.iter().any(|(namespace, _)| namespace.is_none()) {
+                        check_namespace(&mold, meta.namespace());
+                    }
+                    (template, meta)
+                })
+                .collect();
+
+            // a namespace declared by the template takes precedence over the default one
+            let default_namespace = mold.context().default_namespace();
+            let prerendered = prerender(
+                &mold,
+                templates.iter().flat_map(|(template, meta)| {
+                    targets.iter().map(move |(namespace, _)| {
+                        (
+                            template.as_path(),
+                            namespace
+                                .as_deref()
+                                .or_else(|| meta.namespace())
+                                .or(default_namespace),
+                        )
+                    })
+                }),
+                jobs,
+                show_missing,
+            );
+
+            let mut results = vec![];
+            'templates: for (template, meta) in templates {
+                for (namespace, output_path) in &targets {
+                    let namespace = namespace
+                        .as_deref()
+                        .or_else(|| meta.namespace())
+                        .or(default_namespace);
+                    let output_path = match output_path
+                        .as_deref()
+                        .or_else(|| meta.output())
+                        .map(|path| output_for_template(&template, path, &name_rules))
+                        .transpose()
+                    {
+                        Ok(output_path) => output_path,
+                        Err(e) => {
+                            eprintln!("failed to render file `{}` - {}", template.display(), e);
+                            results.push(RenderResult::new(&template, None, Action::Fail));
+                            if fail_fast {
+                                break 'templates;
+                            }
+                            continue;
+                        }
+                    };
+                    let result = render_template(
+                        &mold,
+                        namespace,
+                        &template,
+                        output_path.as_deref(),
+                        &prerendered,
+                        &display_opts,
+                        &write_opts,
+                        &mut state,
+                    );
+                    let failed = result.action == Action::Fail;
+                    results.push(result);
+                    if failed && fail_fast {
+                        break 'templates;
+                    }
+                }
+            }
+            print_diff_total(results.iter().filter_map(|result| result.diff_stat));
+            state.save();
+            if dry_run {
+                print_plan(&results, format);
+            }
+            if let Some(summary) = summary_json {
+                write_summary(&summary, &results, dry_run);
+            }
+            emit_changes(&results, emit_patch.as_deref(), emit_script.as_deref());
+            exit_on_failure(&results);
+            if error_on_missing {
+                exit_on_missing(&results);
+            }
+        }
+        Subcommand::RenderAll {
+            template,
+            context,
+            output_pattern,
+            skip,
+            options,
+        } => {
+            let RenderOptions {
+                show_missing,
+                show_diff,
+                diff_options,
+                line_endings,
+                dry_run,
+                format,
+                force,
+                interactive,
+                header,
+                summary_json,
+                fail_fast,
+            } = options;
+            if !output_pattern.contains(NAMESPACE_PLACEHOLDER) {
+                exit!(
+                    "output pattern `{}` doesn't contain `{}`",
+                    output_pattern,
+                    NAMESPACE_PLACEHOLDER
+                );
+            }
+            let mold = load_mold(&context.paths(), &load_options);
             let display_opts = DisplayOptions {
                 show_missing,
+                warn_missing: !opts.no_warn_missing,
                 show_diff,
+                diff: diff_options,
                 show_headers: false,
                 show_separator: false,
+                quiet,
+            };
+            let write_opts = WriteOptions {
+                dry_run,
+                force,
+                interactive,
+                symlink: false,
+                permissions: None,
+                header,
+                header_comment: None,
+                run_hooks: false,
+                privileged: opts.sudo,
+                elevate_command: &opts.elevate_command,
+                verbatim: false,
+                skip_binary: false,
+                line_endings,
+                fail_fast,
             };
 
-            for (template, output_path) in mold.context().renders() {
-                render_template(
+            let mut results = vec![];
+            for namespace in mold.context().namespace_names() {
+                if skip.iter().any(|skipped| skipped == namespace) {
+                    info!("skipping namespace `{}`", namespace);
+                    continue;
+                }
+                let output_path = output_pattern.replace(NAMESPACE_PLACEHOLDER, namespace);
+                let result = render_template(
                     &mold,
-                    namespace.as_deref(),
-                    template,
-                    Some(output_path),
+                    Some(namespace),
+                    &template,
+                    Some(Path::new(&output_path)),
+                    &Prerendered::default(),
                     &display_opts,
-                    dry_run,
+                    &write_opts,
+                    &mut state,
                 );
+                let failed = result.action == Action::Fail;
+                results.push(result);
+                if failed && fail_fast {
+                    break;
+                }
             }
+            print_diff_total(results.iter().filter_map(|result| result.diff_stat));
+            state.save();
+            if dry_run {
+                print_plan(&results, format);
+            }
+            if let Some(summary) = summary_json {
+                write_summary(&summary, &results, dry_run);
+            }
+            exit_on_failure(&results);
         }
-        Subcommand::Diff {
+        Subcommand::RenderContext {
             context_file,
+            namespace,
+            error_on_missing,
+            exec,
+            symlink,
+            run_hooks,
+            manifest,
+            list,
+            emit_patch,
+            emit_script,
+            skip_binary,
+            jobs,
+            entry_filter,
+            options,
+        } => {
+            let RenderOptions {
+                show_missing,
+                show_diff,
+                diff_options,
+                line_endings,
+                dry_run,
+                format,
+                force,
+                interactive,
+                header,
+                summary_json,
+                fail_fast,
+            } = options;
+            let dry_run = dry_run || emit_patch.is_some() || emit_script.is_some();
+            let context_files = context_file_paths(context_file);
+            let mold = load_mold(&context_files, &load_options);
+            if list {
+                if !list_renders(&mold, &entry_filter) {
+                    std::process::exit(1);
+                }
+                return;
+            }
+            let context_file = &context_files[0];
+            let display_opts = DisplayOptions {
+                show_missing,
+                warn_missing: !opts.no_warn_missing,
+                show_diff,
+                diff: diff_options,
+                show_headers: false,
+                show_separator: false,
+                quiet,
+            };
+            let write_opts = WriteOptions {
+                dry_run,
+                force,
+                interactive,
+                symlink,
+                permissions: None,
+                header,
+                header_comment: None,
+                run_hooks,
+                privileged: opts.sudo,
+                elevate_command: &opts.elevate_command,
+                verbatim: false,
+                skip_binary,
+                line_endings,
+                fail_fast,
+            };
+            let namespace = namespace.or_else(|| {
+                let namespace = applied_namespace(&state, context_file)?;
+                if !quiet {
+                    println!("using namespace `{}` recorded by the last apply", namespace);
+                }
+                Some(namespace)
+            });
+            let namespace = namespace_or_default(&mold, namespace);
+            check_namespace(&mold, namespace.as_deref());
+
+            let results = render_context(
+                &mold,
+                namespace.as_deref(),
+                &rendered_dir(context_file),
+                &entry_filter,
+                jobs,
+                &display_opts,
+                &write_opts,
+                &mut state,
+            );
+            print_diff_total(results.iter().filter_map(|result| result.diff_stat));
+            state.save();
+            if dry_run {
+                print_plan(&results, format);
+            } else if let Some(manifest) = manifest {
+                write_manifest(&manifest, &results);
+            }
+            if let Some(summary) = summary_json {
+                write_summary(&summary, &results, dry_run);
+            }
+            emit_changes(&results, emit_patch.as_deref(), emit_script.as_deref());
+            if let Some(command) = exec {
+                exec_after_render(&command, &results, dry_run);
+            }
+            exit_on_failure(&results);
+            if error_on_missing {
+                exit_on_missing(&results);
+            }
+        }
+        Subcommand::Apply {
+            context,
+            namespace,
+            last,
+            error_on_missing,
+            exec,
+            symlink,
+            run_hooks,
+            manifest,
+            jobs,
+            options,
+        } => {
+            let RenderOptions {
+                show_missing,
+                show_diff,
+                diff_options,
+                line_endings,
+                dry_run,
+                format,
+                force,
+                interactive,
+                header,
+                summary_json,
+                fail_fast,
+            } = options;
+            let (context_files, namespace) = if last {
+                match &state.state().applied {
+                    Some(applied) => (applied.context_files(), applied.namespace.clone()),
+                    None => exit!("no recorded apply found"),
+                }
+            } else {
+                (context.paths(), namespace)
+            };
+            let mold = load_mold(&context_files, &load_options);
+            let context_file = &context_files[0];
+            let namespace = namespace_or_default(&mold, namespace);
+            check_namespace(&mold, namespace.as_deref());
+            let display_opts = DisplayOptions {
+                show_missing,
+                warn_missing: !opts.no_warn_missing,
+                show_diff,
+                diff: diff_options,
+                show_headers: false,
+                show_separator: false,
+                quiet,
+            };
+            let write_opts = WriteOptions {
+                dry_run,
+                force,
+                interactive,
+                symlink,
+                permissions: None,
+                header,
+                header_comment: None,
+                run_hooks,
+                privileged: opts.sudo,
+                elevate_command: &opts.elevate_command,
+                verbatim: false,
+                skip_binary: false,
+                line_endings,
+                fail_fast,
+            };
+
+            let results = render_context(
+                &mold,
+                namespace.as_deref(),
+                &rendered_dir(context_file),
+                &EntryFilter::default(),
+                jobs,
+                &display_opts,
+                &write_opts,
+                &mut state,
+            );
+            if !dry_run {
+                state.record_applied(&context_files, namespace.as_deref());
+            }
+            print_diff_total(results.iter().filter_map(|result| result.diff_stat));
+            state.save();
+            if dry_run {
+                print_plan(&results, format);
+            } else if let Some(manifest) = manifest {
+                write_manifest(&manifest, &results);
+            }
+            if let Some(summary) = summary_json {
+                write_summary(&summary, &results, dry_run);
+            }
+            if let Some(command) = exec {
+                exec_after_render(&command, &results, dry_run);
+            }
+            exit_on_failure(&results);
+            if error_on_missing {
+                exit_on_missing(&results);
+            }
+        }
+        Subcommand::Adopt {
+            file,
             template,
-            output_path,
+            context,
             namespace,
-            show_missing,
+            dry_run,
+            force,
+        } => {
+            let context_file = context_file_path(context.single());
+            let mold = load_mold(std::slice::from_ref(&context_file), &load_options);
+            let namespace = namespace_or_default(&mold, namespace);
+            check_namespace(&mold, namespace.as_deref());
+            let content = match std::fs::read_to_string(expand_path(&file)) {
+                Ok(content) => content,
+                Err(e) => exit!("failed to read `{}` - {:?}", file.display(), e),
+            };
+            let variables: Vec<(String, String)> = mold
+                .context()
+                .resolved_variables(namespace.as_deref())
+                .unwrap_or_default()
+                .into_keys()
+                .filter_map(|name| {
+                    let value = mold.get_variable(name, namespace.as_deref()).ok()??;
+                    Some((name.clone(), value))
+                })
+                .collect();
+            let (adopted, counts) = adopt::substitute(&content, &variables);
+            let substitutions: usize = counts.iter().sum();
+
+            if dry_run {
+                print!("{}", adopted);
+                println!("{:=^1$}", "=", 80);
+                for ((name, value), count) in variables.iter().zip(counts).filter(|(_, c)| *c > 0) {
+                    // short values like `1` are likely to match unrelated text
+                    let warning = if value.chars().count() < 3 {
+                        " (short value, check the matches)".yellow()
+                    } else {
+                        "".normal()
+                    };
+                    println!("{}: {}{}", name, count, warning);
+                }
+                println!("{} substitutions", substitutions);
+                return;
+            }
+            let template_path = expand_path(&template);
+            if template_path.exists() && !force {
+                exit!(
+                    "template `{}` already exists, use `--force` to overwrite it",
+                    template.display()
+                );
+            }
+            let written = template_path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&template_path, adopted));
+            if let Err(e) = written {
+                exit!(
+                    "failed to write template `{}` - {:?}",
+                    template.display(),
+                    e
+                );
+            }
+            let render_key = if template_path.is_relative() {
+                render_key(&context_file, &template_path)
+            } else {
+                template.clone()
+            };
+            if let Err(e) = import::add_render(&context_file, &render_key, &file) {
+                exit!(
+                    "failed to add `{}` to the renders of `{}` - {:?}",
+                    template.display(),
+                    context_file.display(),
+                    e
+                );
+            }
+            println!(
+                "adopted {} as {} with {} substitutions",
+                file.display(),
+                template.display(),
+                substitutions
+            );
+        }
+        Subcommand::Init {
+            path,
+            force,
+            from_dir,
+        } => {
+            let path = match path.map(|path| expand_path(&path)) {
+                Some(path) => path,
+                None => match default_context_files().into_iter().next() {
+                    Some(path) => path,
+                    None => exit!("no location for the context file, specify a path"),
+                },
+            };
+            if path.exists() && !force {
+                exit!(
+                    "`{}` already exists, use `--force` to overwrite it",
+                    path.display()
+                );
+            }
+            let context = match from_dir {
+                Some(dir) => match init::context_from_templates(&expand_path(&dir)) {
+                    Ok(context) => context,
+                    Err(e) => exit!("failed to scan templates in `{}` - {:?}", dir.display(), e),
+                },
+                None => init::EXAMPLE_CONTEXT.to_string(),
+            };
+            if let Err(e) = init::validate(&context) {
+                exit!("{:?}", e);
+            }
+            let written = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&path, context));
+            if let Err(e) = written {
+                exit!("failed to write `{}` - {:?}", path.display(), e);
+            }
+            println!("created {}", path.display());
+        }
+        Subcommand::Current => match &state.state().applied {
+            Some(applied) => {
+                println!(
+                    "Namespace: {}",
+                    applied.namespace.as_deref().unwrap_or(mold::GLOBAL_NS)
+                );
+                println!("Context:   {}", applied.context_file.display());
+                for overlay in &applied.overlays {
+                    println!("Overlay:   {}", overlay.display());
+                }
+                println!("Applied:   {}", applied.timestamp);
+            }
+            None => exit!("no recorded apply found"),
+        },
+        Subcommand::Get {
+            key,
+            context,
+            namespace,
+        } => {
+            let mold = load_mold(&context.paths(), &load_options);
+            let namespace = namespace_or_default(&mold, namespace);
+            check_namespace(&mold, namespace.as_deref());
+            match mold.get_variable(&key, namespace.as_deref()) {
+                Ok(Some(value)) => println!("{}", value),
+                Ok(None) => exit!("variable `{}` is not defined", key),
+                Err(e) => exit!("failed to resolve variable `{}` - {:?}", key, e),
+            }
+        }
+        Subcommand::Explain {
+            key,
+            context,
+            namespace,
+        } => {
+            let mold = load_mold(&context.paths(), &load_options);
+            let namespace = namespace_or_default(&mold, namespace);
+            check_namespace(&mold, namespace.as_deref());
+            match mold.explain_variable(&key, namespace.as_deref()) {
+                Ok(explanation) => {
+                    print_explanation(&explanation, namespace.as_deref(), 0);
+                    if explanation.value.is_none() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => exit!("failed to resolve variable `{}` - {:?}", key, e),
+            }
+        }
+        Subcommand::Shadowing { context, format } => {
+            let mold = load_mold(&context.paths(), &load_options);
+            let shadowed = shadowed_variables(&mold);
+            match format {
+                PlanFormat::Json => match serde_json::to_string_pretty(&shadowed) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => exit!("failed to serialize the shadowed variables - {:?}", e),
+                },
+                PlanFormat::Text => print_shadowed(&shadowed),
+            }
+        }
+        Subcommand::Namespaces { context } => {
+            let mold = load_mold(&context.paths(), &load_options);
+            println!("{}", mold::GLOBAL_NS);
+            for namespace in mold.context().namespace_names() {
+                println!("{}", namespace);
+            }
+        }
+        Subcommand::ImportPalette {
+            context,
+            name,
+            file,
+            format,
+            stdout,
+        } => {
+            let data = match std::fs::read(expand_path(&file)) {
+                Ok(data) => data,
+                Err(e) => exit!("failed to read palette `{}` - {:?}", file.display(), e),
+            };
+            let variables = match palette::parse_palette(&data, format) {
+                Ok(variables) => variables,
+                Err(e) => exit!("failed to import palette `{}` - {:?}", file.display(), e),
+            };
+            import_namespace(
+                context.single(),
+                &name,
+                &variables,
+                import::Existing::Replace,
+                stdout,
+            );
+        }
+        Subcommand::ImportXresources {
+            context,
+            name,
+            file,
+            merge,
+            replace,
+            stdout,
         } => {
-            let mold = match Mold::new(&context_file) {
-                Ok(mold) => mold,
-                Err(e) => exit!("failed to initialize mold - {:?}", e),
+            let content = match std::fs::read_to_string(expand_path(&file)) {
+                Ok(content) => content,
+                Err(e) => exit!("failed to read `{}` - {:?}", file.display(), e),
+            };
+            let xresources = mold::xresources::parse(&content);
+            for unparsed in &xresources.unparsed {
+                warn!(
+                    "skipping line {} of `{}` - {}",
+                    unparsed.line,
+                    file.display(),
+                    unparsed.reason
+                );
+            }
+            let existing = if merge {
+                import::Existing::Merge
+            } else if replace {
+                import::Existing::Replace
+            } else {
+                import::Existing::Error
             };
+            import_namespace(
+                context.single(),
+                &name,
+                &xresources.resources,
+                existing,
+                stdout,
+            );
+        }
+        Subcommand::Status {
+            context,
+            namespace,
+            show_missing,
+            diff,
+            diff_options,
+            header,
+        } => {
+            let context_files = context.paths();
+            let mold = load_mold(&context_files, &load_options);
+            let context_file = &context_files[0];
+            let namespace = namespace_or_default(
+                &mold,
+                namespace.or_else(|| applied_namespace(&state, context_file)),
+            );
+            check_namespace(&mold, namespace.as_deref());
 
-            diff_template(
+            if !print_status(
                 &mold,
-                &template,
-                &output_path,
                 namespace.as_deref(),
+                &rendered_dir(context_file),
                 show_missing,
+                diff.then_some(diff_options),
+                header,
+                &state,
+            ) {
+                std::process::exit(1);
+            }
+        }
+        Subcommand::Verify { manifest } => {
+            let manifest = match Manifest::load(&expand_path(&manifest)) {
+                Ok(manifest) => manifest,
+                Err(e) => exit!("failed to load manifest `{}` - {:?}", manifest.display(), e),
+            };
+            let mut drifted = 0;
+            for (output, drift) in manifest.verify() {
+                let status = match drift {
+                    Drift::Ok => drift.describe().green(),
+                    Drift::Modified => drift.describe().yellow(),
+                    Drift::Missing => drift.describe().red(),
+                };
+                if drift != Drift::Ok {
+                    drifted += 1;
+                }
+                println!("{:<9} {}", status, output.display());
+            }
+            if drifted > 0 {
+                eprintln!("{} outputs changed since the manifest was written", drifted);
+                std::process::exit(1);
+            }
+        }
+        Subcommand::Diff {
+            context,
+            paths,
+            strip_suffix,
+            output_extension,
+            all,
+            diff_options,
+            line_endings,
+            namespace,
+            show_missing,
+        } => {
+            if paths.len() % 2 != 0 {
+                exit!("expected pairs of a template and an output path");
+            }
+            if paths.len() > 2 && paths.iter().step_by(2).any(|t| is_stdio(t)) {
+                exit!("reading a template from standard input can't be mixed with other templates");
+            }
+            let mold = load_mold(&context.paths(), &load_options);
+            let namespace = namespace_or_default(&mold, namespace);
+            check_namespace(&mold, namespace.as_deref());
+
+            let statuses: Vec<_> = if all {
+                mold.render_entries()
+                    .map(|(template, entry)| {
+                        let header = entry.header().then(|| {
+                            entry
+                                .header_comment()
+                                .unwrap_or_else(|| header::comment_leader(entry.output()))
+                        });
+                        diff_template(
+                            &mold,
+                            &template,
+                            entry.output(),
+                            entry.namespace().or(namespace.as_deref()),
+                            show_missing,
+                            header,
+                            line_endings.of_entry(entry),
+                            diff_options,
+                        )
+                    })
+                    .collect()
+            } else {
+                let name_rules = OutputNameRules {
+                    strip_suffix,
+                    extension: output_extension,
+                };
+                let mut pairs = vec![];
+                for pair in paths.chunks(2) {
+                    let (template, output) = (&pair[0], &pair[1]);
+                    let dir = template_path(&mold, template);
+                    if is_stdio(template) || !dir.is_dir() {
+                        pairs.push((template.clone(), output.clone()));
+                        continue;
+                    }
+                    let output = expand_path(output);
+                    if output.exists() && !output.is_dir() {
+                        exit!(
+                            "output path `{}` is not a directory, a directory of templates has to be compared to a directory",
+                            output.display()
+                        );
+                    }
+                    let files = match init::files(&dir) {
+                        Ok(files) => files,
+                        Err(e) => exit!("{:?}", e),
+                    };
+                    pairs.extend(files.iter().map(|file| {
+                        let file = file.strip_prefix(&dir).unwrap_or(file);
+                        let name = name_rules.apply(&file.file_name().unwrap().to_string_lossy());
+                        (template.join(file), output.join(file.with_file_name(name)))
+                    }));
+                }
+                pairs
+                    .iter()
+                    .map(|(template, output)| {
+                        diff_template(
+                            &mold,
+                            template,
+                            output,
+                            namespace.as_deref(),
+                            show_missing,
+                            None,
+                            line_endings,
+                            diff_options,
+                        )
+                    })
+                    .collect()
+            };
+            print_diff_total(statuses.iter().map(|(_, stat)| *stat));
+            if statuses.len() > 1 {
+                let mut counts = std::collections::BTreeMap::new();
+                for (status, _) in &statuses {
+                    *counts.entry(*status).or_insert(0) += 1;
+                }
+                println!("{:=^1$}", "=", 80);
+                let summary = counts
+                    .iter()
+                    .map(|(status, count)| format!("{} {:?}", count, status).to_lowercase())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                println!("{}", summary);
+            }
+            if statuses
+                .iter()
+                .any(|(status, _)| *status != DiffStatus::Unchanged)
+            {
+                std::process::exit(1);
+            }
+        }
+        Subcommand::DiffNs {
+            template,
+            context,
+            from,
+            to,
+            diff_options,
+            show_missing,
+        } => {
+            let mold = load_mold(&context.paths(), &load_options);
+            check_namespace(&mold, Some(&from));
+            check_namespace(&mold, Some(&to));
+
+            match template {
+                Some(template) => {
+                    let stat =
+                        diff_namespaces(&mold, &template, &from, &to, show_missing, diff_options);
+                    if stat.is_some_and(|stat| stat.is_empty()) {
+                        println!("no differences between `{}` and `{}`", from, to);
+                    }
+                }
+                None => {
+                    let summary: Vec<_> = mold
+                        .context()
+                        .renders()
+                        .keys()
+                        .map(|template| {
+                            let template = mold.resolve_render_template(template);
+                            let stat = diff_namespaces(
+                                &mold,
+                                &template,
+                                &from,
+                                &to,
+                                show_missing,
+                                diff_options,
+                            );
+                            (template, stat)
+                        })
+                        .collect();
+                    print_diff_total(summary.iter().filter_map(|(_, stat)| *stat));
+                    println!("{:=^1$}", "=", 80);
+                    for (template, stat) in summary {
+                        let status = match stat {
+                            Some(stat) if stat.is_empty() => "identical".normal(),
+                            Some(stat) => format!("{} lines changed", stat.lines()).yellow(),
+                            None => "failed".red(),
+                        };
+                        println!("{:<18} {}", status, template.display());
+                    }
+                }
+            }
+        }
+        Subcommand::Export {
+            context,
+            namespace,
+            prefix,
+            format,
+            only,
+        } => {
+            let mold = load_mold(&context.paths(), &load_options);
+            let namespace = namespace_or_default(&mold, namespace);
+            check_namespace(&mold, namespace.as_deref());
+            let keys: Vec<String> = if only.is_empty() {
+                match mold.context().resolved_variables(namespace.as_deref()) {
+                    Some(variables) => variables.into_keys().cloned().collect(),
+                    None => exit!(
+                        "namespace `{}` not found in the context",
+                        namespace.unwrap_or_default()
+                    ),
+                }
+            } else {
+                only
+            };
+            let mut exported: Vec<(String, &str)> = vec![];
+            for key in &keys {
+                let value = match mold.get_variable(key, namespace.as_deref()) {
+                    Ok(Some(value)) => value,
+                    Ok(None) => exit!("variable `{}` is not defined", key),
+                    Err(e) => exit!("failed to render variable `{}` - {:?}", key, e),
+                };
+                let name = export::shell_identifier(&prefix, key);
+                if let Some((_, other)) = exported.iter().find(|(exported, _)| *exported == name) {
+                    warn!(
+                        "variables `{}` and `{}` are both exported as `{}`",
+                        other, key, name
+                    );
+                }
+                println!("{}", export::export_line(format, &name, &value));
+                exported.push((name, key));
+            }
+        }
+        Subcommand::Dump { context, namespace } => {
+            let mold = load_mold(&context.paths(), &load_options);
+            let namespace = namespace_or_default(&mold, namespace);
+            check_namespace(&mold, namespace.as_deref());
+            let variables = match mold.context().resolved_variables(namespace.as_deref()) {
+                Some(variables) => variables,
+                None => exit!(
+                    "namespace `{}` not found in the context",
+                    namespace.unwrap_or_default()
+                ),
+            };
+            match serde_yaml::to_string(&variables) {
+                Ok(yaml) => print!("{}", yaml),
+                Err(e) => exit!("failed to serialize variables - {:?}", e),
+            }
+        }
+        Subcommand::Tokens { template, format } => {
+            let input = if is_stdio(&template) {
+                let mut input = String::new();
+                if let Err(e) = io::stdin().read_to_string(&mut input) {
+                    exit!("failed to read template from standard input - {:?}", e);
+                }
+                input
+            } else {
+                let template = match &load_options.template_root {
+                    Some(root) => expand_path(root).join(expand_path(&template)),
+                    None => expand_path(&template),
+                };
+                match std::fs::read_to_string(&template) {
+                    Ok(input) => input,
+                    Err(e) => exit!("failed to read `{}` - {:?}", template.display(), e),
+                }
+            };
+            let tokens = match mold::parse_with_spans(&input) {
+                Ok(tokens) => tokens,
+                Err(e) => exit!("{:?}", e),
+            };
+            match format {
+                PlanFormat::Json => match serde_json::to_string_pretty(&tokens) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => exit!("failed to serialize the tokens - {:?}", e),
+                },
+                PlanFormat::Text => {
+                    let mut counts = std::collections::BTreeMap::new();
+                    print_tokens(&tokens, 0, &mut counts);
+                    let summary = counts
+                        .iter()
+                        .map(|(kind, count)| format!("{} {}", count, kind))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    println!(
+                        "{} tokens{}{}",
+                        counts.values().sum::<usize>(),
+                        if summary.is_empty() { "" } else { ": " },
+                        summary
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_is_valid() {
+        use clap::CommandFactory;
+        Opts::command().debug_assert();
+    }
+
+    #[test]
+    fn context_file_discovery_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let env_file = dir.join("env.yaml");
+        let config_home = dir.join("config");
+        let home = dir.join("home");
+        let locations = || {
+            context_file_locations(
+                Some(env_file.clone().into()),
+                Some(config_home.clone().into()),
+                Some(home.clone()),
+            )
+        };
+        assert_eq!(
+            locations(),
+            [
+                env_file.clone(),
+                config_home.join("mold/context.yaml"),
+                home.join(".config/mold/context.yaml"),
+            ]
+        );
+        assert!(first_context_file(locations()).is_err());
+        for (file, expected) in [
+            (home.join(".config/mold/context.yaml"), 2),
+            (config_home.join("mold/context.yaml"), 1),
+            (env_file.clone(), 0),
+        ] {
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, "namespaces: []").unwrap();
+            assert_eq!(
+                first_context_file(locations()).unwrap(),
+                locations()[expected]
             );
         }
+        assert_eq!(
+            context_file_locations(None, None, Some(home.clone())),
+            [home.join(".config/mold/context.yaml")]
+        );
+    }
+
+    #[test]
+    fn unix_install_args_pass_paths_as_arguments() {
+        let args = unix_install_args(
+            Path::new("/tmp/mold-x/hosts"),
+            Path::new("/etc/my hosts"),
+            0o640,
+        );
+        // the paths are positional parameters of the script, so they are never parsed by the shell
+        assert_eq!(
+            args[4..],
+            ["640", "/tmp/mold-x/hosts", "/etc/my hosts"].map(std::ffi::OsString::from)
+        );
+    }
+
+    #[test]
+    fn windows_install_args_copy_over_output() {
+        let args = windows_install_args(
+            Path::new(r"C:\Temp\mold-x\hosts"),
+            Path::new(r"C:\Windows\System32\drivers\etc\hosts"),
+        );
+        assert_eq!(
+            args,
+            [
+                "cmd",
+                "/C",
+                "copy",
+                "/Y",
+                r"C:\Temp\mold-x\hosts",
+                r"C:\Windows\System32\drivers\etc\hosts"
+            ]
+            .map(std::ffi::OsString::from)
+        );
     }
 }
@@ -0,0 +1,62 @@
+use crate::state::hash;
+use anyhow::{Context as ErrorContext, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// SHA-256 hashes of rendered content keyed by the output path, saved as JSON so that outputs can
+/// later be checked for changes made outside of mold.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    outputs: BTreeMap<PathBuf, String>,
+}
+
+/// Result of comparing an output on disk with its hash in the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drift {
+    Ok,
+    Modified,
+    Missing,
+}
+
+impl Drift {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Drift::Ok => "ok",
+            Drift::Modified => "modified",
+            Drift::Missing => "missing",
+        }
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).context("failed to read manifest")?;
+        let outputs = serde_json::from_slice(&data).context("manifest deserialization error")?;
+        Ok(Self { outputs })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data =
+            serde_json::to_string_pretty(&self.outputs).context("manifest serialization error")?;
+        std::fs::write(path, data + "\n").context("failed to write manifest")
+    }
+
+    pub fn record(&mut self, output: &Path, hash: String) {
+        self.outputs.insert(output.to_path_buf(), hash);
+    }
+
+    /// Compares every output on disk with the recorded hash.
+    pub fn verify(&self) -> Vec<(&Path, Drift)> {
+        self.outputs
+            .iter()
+            .map(|(output, expected)| {
+                let drift = match std::fs::read(output) {
+                    Ok(content) if hash(&content) == *expected => Drift::Ok,
+                    Ok(_) => Drift::Modified,
+                    Err(_) => Drift::Missing,
+                };
+                (output.as_path(), drift)
+            })
+            .collect()
+    }
+}
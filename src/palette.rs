@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Context as ErrorContext, Result};
+use indexmap::IndexMap;
+use serde_yaml::Value;
+
+/// Format of a color palette file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum PaletteFormat {
+    /// `colors.json` generated by pywal with `special` and `colors` sections.
+    Pywal,
+    /// base16 scheme with `base00` to `base0F` colors, either in JSON or YAML.
+    Base16,
+}
+
+/// Terminal colors `color0` to `color15` in terms of base16 colors, as used by base16-shell.
+const BASE16_TERMINAL_COLORS: [&str; 16] = [
+    "base00", "base08", "base0B", "base0A", "base0D", "base0E", "base0C", "base05", "base03",
+    "base08", "base0B", "base0A", "base0D", "base0E", "base0C", "base07",
+];
+
+/// Flattens the palette in `data` to variables named `background`, `foreground`, `cursor` and
+/// `color0` to `color15`.
+pub fn parse_palette(data: &[u8], format: PaletteFormat) -> Result<IndexMap<String, String>> {
+    // JSON is valid YAML so both formats of base16 schemes can be parsed the same way
+    let palette: Value = serde_yaml::from_slice(data).context("palette deserialization error")?;
+    let color = |section: Option<&str>, key: &str| {
+        let value = match section {
+            Some(section) => palette.get(section).and_then(|section| section.get(key)),
+            None => palette.get(key),
+        };
+        value
+            .and_then(Value::as_str)
+            .map(|color| match format {
+                PaletteFormat::Base16 if !color.starts_with('#') => format!("#{}", color),
+                _ => color.to_string(),
+            })
+            .ok_or_else(|| anyhow!("color `{}` missing in the palette", key))
+    };
+
+    let mut variables = IndexMap::new();
+    match format {
+        PaletteFormat::Pywal => {
+            for key in ["background", "foreground", "cursor"] {
+                variables.insert(key.to_string(), color(Some("special"), key)?);
+            }
+            for i in 0..16 {
+                let key = format!("color{}", i);
+                variables.insert(key.clone(), color(Some("colors"), &key)?);
+            }
+        }
+        PaletteFormat::Base16 => {
+            variables.insert("background".to_string(), color(None, "base00")?);
+            variables.insert("foreground".to_string(), color(None, "base05")?);
+            variables.insert("cursor".to_string(), color(None, "base05")?);
+            for (i, base) in BASE16_TERMINAL_COLORS.iter().enumerate() {
+                variables.insert(format!("color{}", i), color(None, base)?);
+            }
+            for i in 0..16 {
+                let key = format!("base{:02X}", i);
+                variables.insert(key.clone(), color(None, &key)?);
+            }
+        }
+    }
+    Ok(variables)
+}
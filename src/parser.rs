@@ -1,12 +1,11 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while},
-    character::complete::char,
-    character::is_alphanumeric,
-    combinator::map,
+    bytes::complete::{tag, take_while1},
+    character::complete::{char, multispace0, multispace1},
+    combinator::{cut, map, opt, peek, recognize},
     error::ErrorKind,
-    multi::{many0, many0_count},
-    sequence::{preceded, terminated, tuple},
+    multi::{many0, separated_list0},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
 };
 
@@ -16,10 +15,38 @@ static FILE_TRIM_START_TAG: &str = "{@~";
 static FILE_TRIM_END_TAG: &str = "~@}";
 static VAR_START_TAG: &str = "{%";
 static VAR_END_TAG: &str = "%}";
+static FILTER_SEPARATOR: char = '|';
+static FILTER_ARGUMENT_SEPARATOR: char = ':';
+static CALL_START: char = '(';
+static CALL_END: char = ')';
+static RAW_KEYWORD: &str = "raw";
+static ENDRAW_KEYWORD: &str = "endraw";
+static IF_EXISTS_KEYWORD: &str = "if_exists";
+static ENDIF_EXISTS_KEYWORD: &str = "endif_exists";
+static IF_KEYWORD: &str = "if";
+static ELSE_KEYWORD: &str = "else";
+static ENDIF_KEYWORD: &str = "endif";
+static NOT_OPERATOR: char = '!';
+static META_START_TAG: &str = "{@meta";
+static META_KEYS: &[&str] = &["output", "namespace"];
+static REQUIRE_START_TAG: &str = "{@require";
+static COMMAND_START_TAG: &str = "{!";
+static COMMAND_END_TAG: &str = "!}";
 
 #[derive(Debug, PartialEq)]
 enum ParseError<I> {
     InputEmpty,
+    UnterminatedRaw(I),
+    UnterminatedIfExists(I),
+    UnterminatedIf(I),
+    DuplicateElse(I),
+    UnexpectedText(I),
+    EmptyVariableName(I),
+    InvalidNameChar(I),
+    UnknownMetaKey(I),
+    MalformedMeta(I),
+    MalformedRequire(I),
+    MismatchedTrim(I),
     Nom(I, ErrorKind),
 }
 
@@ -36,36 +63,135 @@ impl<I> nom::error::ParseError<I> for ParseError<I> {
 #[derive(Debug)]
 pub enum Token<'a> {
     Text(&'a str),
-    Variable { name: &'a str, raw: &'a str },
-    FileSource { path: &'a str, trim: bool },
+    Variable {
+        name: &'a str,
+        filters: Vec<&'a str>,
+        raw: &'a str,
+    },
+    FileSource {
+        path: &'a str,
+        /// Set by the `{@~ ~@}` tags, all leading and trailing whitespace of the file content,
+        /// including line breaks, is removed. The template around the tag is left as it is.
+        trim: bool,
+        filters: Vec<&'a str>,
+    },
+    /// Names of variables that must be defined for the template to render.
+    Require(Vec<&'a str>),
+    /// Block rendered only if the file or directory at `path` exists.
+    IfExists {
+        path: &'a str,
+        body: Vec<Token<'a>>,
+    },
+    /// Block rendered only if the variable `name` is set to a non-empty value, or with
+    /// `negated` only if it isn't, otherwise the `else_body` is rendered.
+    If {
+        name: &'a str,
+        negated: bool,
+        body: Vec<Token<'a>>,
+        else_body: Vec<Token<'a>>,
+    },
+    /// Shell command whose output is inserted in place of the tag.
+    Command(&'a str),
 }
 
 fn is_valid_variable_char(c: char) -> bool {
-    let c = c as u8;
-    is_alphanumeric(c)
-        || c == b'.'
-        || c == b'-'
-        || c == b'_'
-        || c == b'!'
-        || c == b'@'
-        || c == b'$'
-        || c == b'#'
+    c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | '!' | '@' | '$' | '#')
 }
 
 fn variable_name(i: &str) -> IResult<&str, &str, ParseError<&str>> {
-    take_while(is_valid_variable_char)(i)
+    take_while1(is_valid_variable_char)(i)
 }
 
+fn is_valid_filter_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Parses the quoted arguments of a filter call, like `("/", "_")`.
+fn call_arguments(i: &str) -> IResult<&str, Vec<String>, ParseError<&str>> {
+    delimited(
+        pair(char(CALL_START), whitespace),
+        separated_list0(tuple((whitespace, char(','), whitespace)), quoted_string),
+        pair(whitespace, char(CALL_END)),
+    )(i)
+}
+
+/// Parses a filter name with an optional argument, like `indent:4`, or with quoted arguments,
+/// like `replace("/", "_")`.
+fn filter(i: &str) -> IResult<&str, &str, ParseError<&str>> {
+    preceded(
+        tuple((whitespace, char(FILTER_SEPARATOR), whitespace)),
+        recognize(pair(
+            take_while1(is_valid_filter_char),
+            opt(alt((
+                recognize(pair(
+                    char(FILTER_ARGUMENT_SEPARATOR),
+                    take_while1(is_valid_filter_char),
+                )),
+                recognize(call_arguments),
+            ))),
+        )),
+    )(i)
+}
+
+/// Splits a filter into its name and the optional argument. The argument of a filter with
+/// quoted arguments includes the parentheses, see [`filter_arguments`].
+pub fn split_filter(filter: &str) -> (&str, Option<&str>) {
+    match filter.find([FILTER_ARGUMENT_SEPARATOR, CALL_START]) {
+        Some(pos) if filter[pos..].starts_with(FILTER_ARGUMENT_SEPARATOR) => {
+            (&filter[..pos], Some(&filter[pos + 1..]))
+        }
+        Some(pos) => (&filter[..pos], Some(&filter[pos..])),
+        None => (filter, None),
+    }
+}
+
+/// Returns the unquoted arguments of a filter called like `replace("/", "_")` from the argument
+/// returned by [`split_filter`], or `None` if they are not quoted arguments in parentheses.
+pub fn filter_arguments(argument: &str) -> Option<Vec<String>> {
+    match call_arguments(argument) {
+        Ok(("", arguments)) => Some(arguments),
+        _ => None,
+    }
+}
+
+/// Splits the content of a file source tag into the path and the filters following it.
+fn file_source(content: &str, trim: bool) -> Token<'_> {
+    let (path, filters) = match content.find(FILTER_SEPARATOR) {
+        Some(pos) => match many0(filter)(&content[pos..]) {
+            Ok((rest, filters)) if rest.trim().is_empty() => (&content[..pos], filters),
+            // malformed filters are kept so that rendering reports them
+            _ => (
+                &content[..pos],
+                content[pos + 1..]
+                    .split(FILTER_SEPARATOR)
+                    .map(str::trim)
+                    .collect(),
+            ),
+        },
+        None => (content, vec![]),
+    };
+    Token::FileSource {
+        path: path.trim(),
+        trim,
+        filters,
+    }
+}
+
+/// Takes the path up to `end_tag` which has to be on the same line before any other tag. A start
+/// tag that is never closed is left as text just like an unclosed variable tag, so literal `{@`
+/// sequences don't need escaping, but a trim tag closed with a plain end tag is an error.
 fn file_path_impl<'a>(
     i: &'a str,
     end_tag: &'static str,
 ) -> IResult<&'a str, &'a str, ParseError<&'a str>> {
-    if let Some(pos) = i.find(end_tag) {
-        let trimmed = i.split(end_tag).next().unwrap().trim();
-
-        Ok((&i[pos..], trimmed))
+    let line = i.split('\n').next().unwrap_or_default();
+    let line = &line[..find_tag_start(line).unwrap_or(line.len())];
+    if let Some(pos) = line.find(end_tag) {
+        Ok((&i[pos..], line[..pos].trim()))
+    } else if line.contains(FILE_END_TAG) {
+        Err(nom::Err::Failure(ParseError::MismatchedTrim(i)))
     } else {
-        Err(nom::Err::Failure(ParseError::Nom(i, ErrorKind::Verify)))
+        Err(nom::Err::Error(ParseError::Nom(i, ErrorKind::Verify)))
     }
 }
 
@@ -77,8 +203,9 @@ fn file_path_trim(i: &str) -> IResult<&str, &str, ParseError<&str>> {
     file_path_impl(i, FILE_TRIM_END_TAG)
 }
 
-fn space_count(i: &str) -> IResult<&str, usize, ParseError<&str>> {
-    many0_count(char(' '))(i)
+/// Whitespace allowed inside of tags, that is spaces, tabs and newlines.
+fn whitespace(i: &str) -> IResult<&str, &str, ParseError<&str>> {
+    multispace0(i)
 }
 
 fn parse_enclosed_tag<'a>(
@@ -86,80 +213,830 @@ fn parse_enclosed_tag<'a>(
     start_tag: &'static str,
     end_tag: &'static str,
     take_while: impl FnMut(&'a str) -> IResult<&'a str, &'a str, ParseError<&'a str>>,
-    f: impl FnMut((usize, &'a str, usize)) -> Token<'a>,
+    f: impl FnMut(&'a str) -> Token<'a>,
 ) -> IResult<&'a str, Token<'a>, ParseError<&'a str>> {
     map(
-        tuple((
-            preceded(tag(start_tag), space_count),
+        delimited(
+            pair(tag(start_tag), whitespace),
             take_while,
-            terminated(space_count, tag(end_tag)),
-        )),
+            pair(whitespace, tag(end_tag)),
+        ),
         f,
     )(i)
 }
 
-fn parse_variable(i: &str) -> IResult<&str, Token, ParseError<&str>> {
-    parse_enclosed_tag(
-        i,
-        VAR_START_TAG,
-        VAR_END_TAG,
-        variable_name,
-        |(count1, name, count2)| Token::Variable {
+/// Returns the content of a variable tag that is closed on the same line, used to tell apart
+/// malformed tags from text that merely contains the start tag.
+fn enclosed_content(i: &str) -> Option<&str> {
+    let line = i.split('\n').next().unwrap_or_default();
+    let content = &line[..line.find(VAR_END_TAG)?];
+    if content.contains(VAR_START_TAG) {
+        return None;
+    }
+    Some(content)
+}
+
+fn parse_variable(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
+    let (rest, _) = preceded(tag(VAR_START_TAG), whitespace)(i)?;
+    let (rest, (name, filters)) = match pair(variable_name, many0(filter))(rest) {
+        Ok(parsed) => parsed,
+        Err(nom::Err::Error(e)) => {
+            return Err(match enclosed_content(rest) {
+                Some("") => nom::Err::Failure(ParseError::EmptyVariableName(i)),
+                Some(_) => nom::Err::Failure(ParseError::InvalidNameChar(rest)),
+                None => nom::Err::Error(e),
+            })
+        }
+        Err(e) => return Err(e),
+    };
+    let rest = match terminated(whitespace, tag(VAR_END_TAG))(rest) {
+        Ok((rest, _)) => rest,
+        Err(nom::Err::Error(e)) => {
+            let trimmed = rest.trim_start();
+            return Err(match enclosed_content(trimmed) {
+                // a character that can't be a part of a name splits it, like in `{% my var %}`
+                Some(content)
+                    if filters.is_empty()
+                        && (trimmed.len() == rest.len()
+                            || content.starts_with(is_valid_variable_char)) =>
+                {
+                    nom::Err::Failure(ParseError::InvalidNameChar(rest))
+                }
+                Some(content) if !content.is_empty() => {
+                    nom::Err::Failure(ParseError::UnexpectedText(trimmed))
+                }
+                _ => nom::Err::Error(e),
+            });
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok((
+        rest,
+        Token::Variable {
             name,
-            raw: &i[..name.len() + 4 + count1 + count2],
+            filters,
+            raw: consumed(i, rest),
         },
-    )
+    ))
 }
 
-fn parse_file_source(i: &str) -> IResult<&str, Token, ParseError<&str>> {
-    parse_enclosed_tag(
-        i,
-        FILE_START_TAG,
-        FILE_END_TAG,
-        file_path,
-        |(_, path, _)| Token::FileSource { path, trim: false },
-    )
+fn parse_file_source(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
+    parse_enclosed_tag(i, FILE_START_TAG, FILE_END_TAG, file_path, |content| {
+        file_source(content, false)
+    })
 }
 
-fn parse_file_source_trim(i: &str) -> IResult<&str, Token, ParseError<&str>> {
+fn parse_file_source_trim(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
     parse_enclosed_tag(
         i,
         FILE_TRIM_START_TAG,
         FILE_TRIM_END_TAG,
         file_path_trim,
-        |(_, path, _)| Token::FileSource { path, trim: true },
+        |content| file_source(content, true),
+    )
+    // the error points at the start tag rather than at the path
+    .map_err(|e| match e {
+        nom::Err::Failure(ParseError::MismatchedTrim(_)) => {
+            nom::Err::Failure(ParseError::MismatchedTrim(i))
+        }
+        e => e,
+    })
+}
+
+/// Parses a command tag like `{! git rev-parse HEAD !}`. The tag has to be closed on the same
+/// line, otherwise it's left as text as `{!` is common in shell scripts, like in `${!name}`.
+fn parse_command(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
+    parse_enclosed_tag(
+        i,
+        COMMAND_START_TAG,
+        COMMAND_END_TAG,
+        |i| match i
+            .split('\n')
+            .next()
+            .unwrap_or_default()
+            .find(COMMAND_END_TAG)
+        {
+            Some(pos) => Ok((&i[pos..], i[..pos].trim())),
+            None => Err(nom::Err::Error(ParseError::Nom(i, ErrorKind::TakeUntil))),
+        },
+        Token::Command,
     )
 }
 
-#[inline]
-fn is_not_variable_start(chr: char) -> bool {
-    chr != '{'
+fn keyword_tag<'a>(
+    i: &'a str,
+    keyword: &'static str,
+) -> IResult<&'a str, &'a str, ParseError<&'a str>> {
+    recognize(tuple((
+        tag(VAR_START_TAG),
+        whitespace,
+        tag(keyword),
+        whitespace,
+        tag(VAR_END_TAG),
+    )))(i)
+}
+
+fn parse_raw_block(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
+    let (body, _) = keyword_tag(i, RAW_KEYWORD)?;
+
+    let mut offset = 0;
+    while let Some(pos) = body[offset..].find(VAR_START_TAG) {
+        let start = offset + pos;
+        if let Ok((rest, _)) = keyword_tag(&body[start..], ENDRAW_KEYWORD) {
+            return Ok((rest, Token::Text(&body[..start])));
+        }
+        offset = start + VAR_START_TAG.len();
+    }
+
+    Err(nom::Err::Failure(ParseError::UnterminatedRaw(i)))
+}
+
+/// Parses a double quoted string, a backslash escapes the following character.
+fn quoted_string(i: &str) -> IResult<&str, String, ParseError<&str>> {
+    let (rest, _) = char('"')(i)?;
+    let mut value = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '"' => return Ok((&rest[pos + 1..], value)),
+            '\\' => match chars.next() {
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(nom::Err::Error(ParseError::Nom(i, ErrorKind::Char)))
 }
 
-fn parse_text(i: &str) -> IResult<&str, Token, ParseError<&str>> {
+fn meta_key(i: &str) -> IResult<&str, &str, ParseError<&str>> {
+    let (rest, key) = take_while1(is_valid_filter_char)(i)?;
+    if !META_KEYS.contains(&key) {
+        return Err(nom::Err::Failure(ParseError::UnknownMetaKey(i)));
+    }
+    Ok((rest, key))
+}
+
+fn meta_entry(i: &str) -> IResult<&str, (&str, String), ParseError<&str>> {
+    separated_pair(
+        meta_key,
+        tuple((whitespace, char('='), whitespace)),
+        cut(quoted_string),
+    )(i)
+}
+
+/// Skips the line break following a directive so that it doesn't leave an empty line behind.
+fn skip_line_break(i: &str) -> &str {
+    i.strip_prefix("\r\n")
+        .or_else(|| i.strip_prefix('\n'))
+        .unwrap_or(i)
+}
+
+/// Key-value pairs declared in a front-matter block.
+pub type MetaEntries<'a> = Vec<(&'a str, String)>;
+
+/// Parses a front-matter block like `{@meta output = "out.conf", namespace = "dark" @}`.
+fn parse_meta_block(i: &str) -> IResult<&str, MetaEntries<'_>, ParseError<&str>> {
+    let (rest, _) = terminated(
+        tag(META_START_TAG),
+        peek(alt((multispace1, tag(FILE_END_TAG)))),
+    )(i)?;
+    let (rest, entries) = cut(delimited(
+        whitespace,
+        separated_list0(tuple((whitespace, char(','), whitespace)), meta_entry),
+        pair(whitespace, tag(FILE_END_TAG)),
+    ))(rest)
+    .map_err(|e| match e {
+        nom::Err::Failure(ParseError::Nom(rest, _)) => {
+            nom::Err::Failure(ParseError::MalformedMeta(rest))
+        }
+        e => e,
+    })?;
+    Ok((skip_line_break(rest), entries))
+}
+
+/// Splits the front-matter block from the start of `input`. Returns the declared key-value
+/// pairs, if there is a front-matter, and the rest of the input.
+pub fn parse_meta(input: &str) -> anyhow::Result<(Option<MetaEntries<'_>>, &str)> {
+    match parse_meta_block(input) {
+        Ok((rest, entries)) => Ok((Some(entries), rest)),
+        Err(nom::Err::Error(_)) => Ok((None, input)),
+        Err(e) => Err(error_message(input, e)),
+    }
+}
+
+fn parse_require(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
+    let (rest, _) = terminated(tag(REQUIRE_START_TAG), peek(multispace1))(i)?;
+    cut(delimited(
+        whitespace,
+        separated_list0(
+            tuple((whitespace, char(','), whitespace)),
+            take_while1(is_valid_variable_char),
+        ),
+        pair(whitespace, tag(FILE_END_TAG)),
+    ))(rest)
+    .map(|(rest, names)| (skip_line_break(rest), Token::Require(names)))
+    .map_err(|e| match e {
+        nom::Err::Failure(ParseError::Nom(rest, _)) => {
+            nom::Err::Failure(ParseError::MalformedRequire(rest))
+        }
+        e => e,
+    })
+}
+
+/// Parses the opening tag of a conditional block like `{% if_exists ~/.config/foo %}` returning
+/// the path.
+fn if_exists_tag(i: &str) -> IResult<&str, &str, ParseError<&str>> {
+    let (rest, _) = tuple((
+        tag(VAR_START_TAG),
+        whitespace,
+        tag(IF_EXISTS_KEYWORD),
+        multispace1,
+    ))(i)?;
+    match rest.find(VAR_END_TAG) {
+        Some(end) if !rest[..end].trim().is_empty() => {
+            Ok((&rest[end + VAR_END_TAG.len()..], rest[..end].trim()))
+        }
+        _ => Err(nom::Err::Error(ParseError::Nom(i, ErrorKind::Verify))),
+    }
+}
+
+fn parse_if_exists(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
+    let (rest, (path, body)) = if_exists_block(i)?;
+    let (_, body) = many0(parse_token)(body)?;
+    Ok((rest, Token::IfExists { path, body }))
+}
+
+/// Parses a conditional block returning the path and the unparsed body.
+fn if_exists_block(i: &str) -> IResult<&str, (&str, &str), ParseError<&str>> {
+    let (body, path) = if_exists_tag(i)?;
+
+    // nested blocks have to be skipped to find the matching end tag
+    let mut depth = 0;
+    let mut offset = 0;
+    while let Some(pos) = body[offset..].find(VAR_START_TAG) {
+        let start = offset + pos;
+        // tags inside of raw blocks are just text
+        if let Ok((rest, _)) = parse_raw_block(&body[start..]) {
+            offset = body.len() - rest.len();
+            continue;
+        }
+        if let Ok((rest, _)) = keyword_tag(&body[start..], ENDIF_EXISTS_KEYWORD) {
+            if depth == 0 {
+                return Ok((rest, (path, &body[..start])));
+            }
+            depth -= 1;
+        } else if if_exists_tag(&body[start..]).is_ok() {
+            depth += 1;
+        }
+        offset = start + VAR_START_TAG.len();
+    }
+
+    Err(nom::Err::Failure(ParseError::UnterminatedIfExists(i)))
+}
+
+/// Parses the opening tag of a conditional block like `{% if name %}` or `{% if !name %}`
+/// returning the variable name and whether the condition is negated.
+fn if_tag(i: &str) -> IResult<&str, (&str, bool), ParseError<&str>> {
+    let (rest, (_, _, _, _, negated, _, name, _, _)) = tuple((
+        tag(VAR_START_TAG),
+        whitespace,
+        tag(IF_KEYWORD),
+        multispace1,
+        opt(char(NOT_OPERATOR)),
+        whitespace,
+        take_while1(is_valid_variable_char),
+        whitespace,
+        tag(VAR_END_TAG),
+    ))(i)?;
+    Ok((rest, (name, negated.is_some())))
+}
+
+fn parse_if(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
+    let (rest, ((name, negated), body, else_body)) = if_block(i)?;
+    let (_, body) = many0(parse_token)(body)?;
+    let (_, else_body) = many0(parse_token)(else_body.unwrap_or_default())?;
+    Ok((
+        rest,
+        Token::If {
+            name,
+            negated,
+            body,
+            else_body,
+        },
+    ))
+}
+
+/// Body of a conditional block and the body of its optional `{% else %}` branch, both unparsed.
+type IfBlock<'a> = ((&'a str, bool), &'a str, Option<&'a str>);
+
+/// Parses a variable conditional block returning the condition, the unparsed body and the
+/// unparsed body of the `{% else %}` branch if there is one.
+fn if_block(i: &str) -> IResult<&str, IfBlock<'_>, ParseError<&str>> {
+    let (body, condition) = if_tag(i)?;
+
+    let mut else_branch = None;
+    let mut offset = 0;
+    while let Some(pos) = body[offset..].find(VAR_START_TAG) {
+        let start = offset + pos;
+        // raw and nested blocks are skipped as a whole, tags inside of them belong to them
+        if let Ok((rest, _)) = parse_raw_block(&body[start..]) {
+            offset = body.len() - rest.len();
+            continue;
+        }
+        if if_exists_tag(&body[start..]).is_ok() {
+            let (rest, _) = if_exists_block(&body[start..])?;
+            offset = body.len() - rest.len();
+            continue;
+        }
+        if if_tag(&body[start..]).is_ok() {
+            let (rest, _) = if_block(&body[start..])?;
+            offset = body.len() - rest.len();
+            continue;
+        }
+        if let Ok((rest, _)) = keyword_tag(&body[start..], ENDIF_KEYWORD) {
+            return Ok(match else_branch {
+                Some((end, else_start)) => (
+                    rest,
+                    (condition, &body[..end], Some(&body[else_start..start])),
+                ),
+                None => (rest, (condition, &body[..start], None)),
+            });
+        }
+        if let Ok((rest, _)) = keyword_tag(&body[start..], ELSE_KEYWORD) {
+            if else_branch.is_some() {
+                return Err(nom::Err::Failure(ParseError::DuplicateElse(&body[start..])));
+            }
+            else_branch = Some((start, body.len() - rest.len()));
+            offset = body.len() - rest.len();
+            continue;
+        }
+        offset = start + VAR_START_TAG.len();
+    }
+
+    Err(nom::Err::Failure(ParseError::UnterminatedIf(i)))
+}
+
+fn is_tag_start(i: &str) -> bool {
+    i.starts_with(VAR_START_TAG)
+        || i.starts_with(FILE_START_TAG)
+        || i.starts_with(COMMAND_START_TAG)
+}
+
+/// Finds the position of the next `{` that starts a tag, ordinary braces are skipped.
+fn find_tag_start(i: &str) -> Option<usize> {
+    i.match_indices('{')
+        .map(|(pos, _)| pos)
+        .find(|&pos| is_tag_start(&i[pos..]))
+}
+
+fn parse_text(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
     if i.is_empty() {
         return Err(nom::Err::Error(ParseError::InputEmpty));
     }
+    if is_tag_start(i) {
+        return Err(nom::Err::Error(ParseError::Nom(i, ErrorKind::TakeUntil)));
+    }
 
-    map(take_while(is_not_variable_start), Token::Text)(i)
+    let end = find_tag_start(i).unwrap_or(i.len());
+    Ok((&i[end..], Token::Text(&i[..end])))
 }
 
-fn parse_brace(i: &str) -> IResult<&str, Token, ParseError<&str>> {
+fn parse_brace(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
     map(tag("{"), Token::Text)(i)
 }
 
-fn parse_token(i: &str) -> IResult<&str, Token, ParseError<&str>> {
+fn parse_token(i: &str) -> IResult<&str, Token<'_>, ParseError<&str>> {
     alt((
+        parse_raw_block,
+        parse_if_exists,
+        parse_if,
         parse_variable,
+        parse_require,
         parse_file_source_trim,
         parse_file_source,
-        parse_brace,
+        parse_command,
         parse_text,
+        parse_brace,
     ))(i)
 }
 
-pub fn parse_input(i: &str) -> anyhow::Result<Vec<Token>> {
+fn contains_tags(i: &str) -> bool {
+    i.contains(VAR_START_TAG) || i.contains(FILE_START_TAG) || i.contains(COMMAND_START_TAG)
+}
+
+pub fn parse_input(input: &str) -> anyhow::Result<Vec<Token<'_>>> {
+    // front-matter is not a part of the rendered output
+    let (_, i) = parse_meta(input)?;
+
+    // plain files without any tags don't have to go through the parser
+    if !contains_tags(i) {
+        return Ok(if i.is_empty() {
+            vec![]
+        } else {
+            vec![Token::Text(i)]
+        });
+    }
+
     many0(parse_token)(i)
         .map(|(_, tokens)| tokens)
-        .map_err(|e| anyhow::anyhow!("{}", e))
+        .map_err(|e| error_message(input, e))
+}
+
+/// A token together with its location in the parsed input.
+pub struct Spanned<'a> {
+    /// Byte offset of the start of the token.
+    pub offset: usize,
+    /// Byte offset just past the end of the token, including a line break skipped after a
+    /// directive.
+    pub end: usize,
+    /// 1-based line and column of the start of the token.
+    pub line: usize,
+    pub column: usize,
+    pub token: Token<'a>,
+    /// Located tokens of the body of a conditional block.
+    pub body: Vec<Spanned<'a>>,
+    /// Located tokens of the `{% else %}` branch of a variable conditional block.
+    pub else_body: Vec<Spanned<'a>>,
+}
+
+/// Parses `input` like [`parse_input`] recording where every token starts, tokens in the body
+/// of conditional blocks are located as well.
+pub fn parse_with_spans(input: &str) -> anyhow::Result<Vec<Spanned<'_>>> {
+    let (_, i) = parse_meta(input)?;
+    spans(input, i).map_err(|e| error_message(input, e))
+}
+
+fn spans<'a>(
+    input: &'a str,
+    mut i: &'a str,
+) -> Result<Vec<Spanned<'a>>, nom::Err<ParseError<&'a str>>> {
+    let mut spanned = vec![];
+    loop {
+        let (rest, token) = match parse_token(i) {
+            Ok(parsed) => parsed,
+            Err(nom::Err::Error(_)) => return Ok(spanned),
+            Err(e) => return Err(e),
+        };
+        let (body, else_body) = match token {
+            Token::IfExists { .. } => {
+                let (_, (_, body)) = if_exists_block(i)?;
+                (spans(input, body)?, vec![])
+            }
+            Token::If { .. } => {
+                let (_, (_, body, else_body)) = if_block(i)?;
+                (
+                    spans(input, body)?,
+                    spans(input, else_body.unwrap_or_default())?,
+                )
+            }
+            _ => (vec![], vec![]),
+        };
+        let (line, column) = location(input, i);
+        spanned.push(Spanned {
+            offset: consumed(input, i).len(),
+            end: consumed(input, rest).len(),
+            line,
+            column,
+            token,
+            body,
+            else_body,
+        });
+        i = rest;
+    }
+}
+
+/// Converts a parser error to a message with the location of the error in `input`.
+fn error_message(input: &str, e: nom::Err<ParseError<&str>>) -> anyhow::Error {
+    match e {
+        nom::Err::Failure(ParseError::UnterminatedRaw(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "unterminated `{{% {} %}}` block at line {}, column {}",
+                RAW_KEYWORD,
+                line,
+                column
+            )
+        }
+        nom::Err::Failure(ParseError::UnterminatedIfExists(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "unterminated `{{% {} %}}` block at line {}, column {}",
+                IF_EXISTS_KEYWORD,
+                line,
+                column
+            )
+        }
+        nom::Err::Failure(ParseError::UnterminatedIf(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "unterminated `{{% {} %}}` block at line {}, column {}, expected `{{% {} %}}`",
+                IF_KEYWORD,
+                line,
+                column,
+                ENDIF_KEYWORD
+            )
+        }
+        nom::Err::Failure(ParseError::DuplicateElse(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "second `{{% {} %}}` of a block at line {}, column {}",
+                ELSE_KEYWORD,
+                line,
+                column
+            )
+        }
+        nom::Err::Failure(ParseError::EmptyVariableName(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "empty variable name at line {}, column {}, use a raw block to output the tag as is",
+                line,
+                column
+            )
+        }
+        nom::Err::Failure(ParseError::InvalidNameChar(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "invalid character {:?} in variable name at line {}, column {}",
+                rest.chars().next().unwrap_or_default(),
+                line,
+                column
+            )
+        }
+        nom::Err::Failure(ParseError::UnexpectedText(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "unexpected text `{}` after variable name at line {}, column {}",
+                enclosed_content(rest).unwrap_or(rest).trim_end(),
+                line,
+                column
+            )
+        }
+        nom::Err::Failure(ParseError::UnknownMetaKey(rest)) => {
+            let (line, column) = location(input, rest);
+            let key = take_while1::<_, _, ParseError<&str>>(is_valid_filter_char)(rest)
+                .map(|(_, key)| key)
+                .unwrap_or(rest);
+            anyhow::anyhow!(
+                "unknown key `{}` in meta block at line {}, column {}, expected one of: {}",
+                key,
+                line,
+                column,
+                META_KEYS.join(", ")
+            )
+        }
+        nom::Err::Failure(ParseError::MalformedMeta(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "malformed meta block at line {}, column {}, expected `key = \"value\"` pairs separated by commas",
+                line,
+                column
+            )
+        }
+        nom::Err::Failure(ParseError::MalformedRequire(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "malformed require directive at line {}, column {}, expected variable names separated by commas",
+                line,
+                column
+            )
+        }
+        nom::Err::Failure(ParseError::MismatchedTrim(rest)) => {
+            let (line, column) = location(input, rest);
+            anyhow::anyhow!(
+                "file source tag at line {}, column {} starts with `{}` but ends with `{}`, expected `{}`",
+                line,
+                column,
+                FILE_TRIM_START_TAG,
+                FILE_END_TAG,
+                FILE_TRIM_END_TAG
+            )
+        }
+        e => anyhow::anyhow!("{}", e),
+    }
+}
+
+/// Returns the part of `input` preceding `rest`, which has to be a slice of `input`. This is the
+/// part consumed by a parser that returned `rest` as the remaining input. As `rest` is a slice
+/// of `input` the offset lies on a char boundary regardless of the delimiters and whitespace
+/// that were consumed, even for slices of nested blocks.
+fn consumed<'a>(input: &'a str, rest: &'a str) -> &'a str {
+    &input[..rest.as_ptr() as usize - input.as_ptr() as usize]
+}
+
+/// Returns the 1-based line and column at which `rest` starts in `input`.
+pub fn location(input: &str, rest: &str) -> (usize, usize) {
+    let consumed = consumed(input, rest);
+    let line = consumed.matches('\n').count() + 1;
+    let column = consumed
+        .rfind('\n')
+        .map(|pos| consumed[pos + 1..].chars().count())
+        .unwrap_or_else(|| consumed.chars().count())
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Joins the text and the raw variable tags of `tokens`, which gives back the parsed input.
+    fn unparse(tokens: &[Token<'_>]) -> String {
+        tokens
+            .iter()
+            .map(|token| match token {
+                Token::Text(text) => *text,
+                Token::Variable { raw, .. } => *raw,
+                token => panic!("unexpected token {:?}", token),
+            })
+            .collect()
+    }
+
+    fn error(input: &str) -> String {
+        parse_input(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn variables_round_trip() {
+        for input in [
+            "a {% name %} b",
+            "{%name%}",
+            "{%  name | indent:2 %}\n",
+            "x {% a %}{% b %} y { z }",
+            r#"{% path | replace("/", "_") %}"#,
+        ] {
+            assert_eq!(unparse(&parse_input(input).unwrap()), input);
+        }
+    }
+
+    #[test]
+    fn variable_with_filters() {
+        match &parse_input(r#"{% path | replace("/", "_") | indent:2 %}"#).unwrap()[..] {
+            [Token::Variable { name, filters, .. }] => {
+                assert_eq!(*name, "path");
+                assert_eq!(filters, &[r#"replace("/", "_")"#, "indent:2"]);
+            }
+            tokens => panic!("unexpected tokens {:?}", tokens),
+        }
+    }
+
+    #[test]
+    fn unexpected_text_after_variable_name() {
+        assert_eq!(
+            error("a\n{% name \"x\" %}"),
+            "unexpected text `\"x\"` after variable name at line 2, column 9"
+        );
+        assert_eq!(
+            error("{% name | indent:2 extra %}"),
+            "unexpected text `extra` after variable name at line 1, column 20"
+        );
+    }
+
+    #[test]
+    fn raw_tag_with_multibyte_content_around_it() {
+        let input = "żółć{% ключ %}日本{%\tname\n%}ü";
+        let tokens = parse_input(input).unwrap();
+        assert_eq!(unparse(&tokens), input);
+        match &tokens[..] {
+            [Token::Text("żółć"), Token::Variable {
+                name: "ключ",
+                raw: first,
+                ..
+            }, Token::Text("日本"), Token::Variable {
+                name: "name",
+                raw: second,
+                ..
+            }, Token::Text("ü")] => {
+                assert_eq!(*first, "{% ключ %}");
+                assert_eq!(*second, "{%\tname\n%}");
+            }
+            tokens => panic!("unexpected tokens {:?}", tokens),
+        }
+    }
+
+    #[test]
+    fn error_location_counts_characters() {
+        assert_eq!(
+            error("ąę\n日本 {% a b %}"),
+            "invalid character ' ' in variable name at line 2, column 8"
+        );
+    }
+
+    #[test]
+    fn if_exists_block() {
+        match &parse_input("{% if_exists ~/.config/foo %}a{% x %}{% endif_exists %}b").unwrap()[..]
+        {
+            [Token::IfExists { path, body }, Token::Text("b")] => {
+                assert_eq!(*path, "~/.config/foo");
+                assert_eq!(unparse(body), "a{% x %}");
+            }
+            tokens => panic!("unexpected tokens {:?}", tokens),
+        }
+    }
+
+    #[test]
+    fn nested_if_exists_blocks() {
+        let input = "{% if_exists /a %}{% if_exists /b %}x{% endif_exists %}y{% endif_exists %}";
+        match &parse_input(input).unwrap()[..] {
+            [Token::IfExists { path: "/a", body }] => match &body[..] {
+                [Token::IfExists { path: "/b", body }, Token::Text("y")] => {
+                    assert_eq!(unparse(body), "x")
+                }
+                body => panic!("unexpected body {:?}", body),
+            },
+            tokens => panic!("unexpected tokens {:?}", tokens),
+        }
+    }
+
+    #[test]
+    fn raw_block_inside_if_exists() {
+        let input = "{% if_exists /a %}{% raw %}{% endif_exists %}{% endraw %}{% endif_exists %}";
+        match &parse_input(input).unwrap()[..] {
+            [Token::IfExists { body, .. }] => assert_eq!(unparse(body), "{% endif_exists %}"),
+            tokens => panic!("unexpected tokens {:?}", tokens),
+        }
+    }
+
+    #[test]
+    fn unterminated_if_exists_block() {
+        assert_eq!(
+            error("a\n{% if_exists /a %}{% if_exists /b %}{% endif_exists %}"),
+            "unterminated `{% if_exists %}` block at line 2, column 1"
+        );
+    }
+
+    #[test]
+    fn unclosed_variable_tag_is_text() {
+        for input in ["{% name", "50% {% off", "50% {% off\nall day", "{% name |"] {
+            let tokens = parse_input(input).unwrap();
+            assert!(
+                tokens.iter().all(|token| matches!(token, Token::Text(_))),
+                "{:?}",
+                tokens
+            );
+            assert_eq!(unparse(&tokens), input);
+        }
+        // whitespace in tags includes line breaks so this one is closed
+        assert!(matches!(
+            parse_input("50% {% off\n%}").unwrap()[..],
+            [Token::Text("50% "), Token::Variable { name: "off", .. }]
+        ));
+    }
+
+    #[test]
+    fn empty_variable_name_is_an_error() {
+        for input in ["{%%}", "{% %}"] {
+            assert_eq!(
+                error(input),
+                "empty variable name at line 1, column 1, use a raw block to output the tag as is"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_character_in_variable_name_is_an_error() {
+        assert_eq!(
+            error("{%my var%}"),
+            "invalid character ' ' in variable name at line 1, column 5"
+        );
+        assert_eq!(
+            error("a\n  {% my var %}"),
+            "invalid character ' ' in variable name at line 2, column 8"
+        );
+    }
+
+    #[test]
+    fn unclosed_empty_tag_is_text() {
+        assert_eq!(unparse(&parse_input("{%%").unwrap()), "{%%");
+    }
+
+    #[test]
+    fn unclosed_file_source_tag_is_text() {
+        for (input, expected) in [
+            ("{@ path", vec![]),
+            ("email {@ me\n", vec![]),
+            ("mail {@ me\nlater {@ x @}", vec!["x"]),
+            ("mail {@~ me\nlater {@ x @}", vec!["x"]),
+            ("mail {@ me {% name %} @}", vec![]),
+        ] {
+            let paths: Vec<_> = parse_input(input)
+                .unwrap()
+                .into_iter()
+                .filter_map(|token| match token {
+                    Token::FileSource { path, .. } => Some(path),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(paths, expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn trim_file_source_tag_closed_with_plain_end_tag() {
+        assert_eq!(
+            error("a\nb {@~ me @}"),
+            "file source tag at line 2, column 3 starts with `{@~` but ends with `@}`, expected `~@}`"
+        );
+    }
 }
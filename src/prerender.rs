@@ -0,0 +1,52 @@
+use log::info;
+use mold::{Mold, Rendered};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Templates rendered ahead of time on a thread pool, keyed by the resolved template path and
+/// the namespace they were rendered with.
+///
+/// Only the rendering runs in parallel, writing the outputs, printing diffs and updating the
+/// state stays serial so that the output is in the same order as without `--jobs`. Templates
+/// that failed to render are left out and rendered again serially to report the error in order.
+#[derive(Default)]
+pub struct Prerendered(HashMap<(PathBuf, Option<String>), Rendered>);
+
+impl Prerendered {
+    /// Renders all `templates` using `jobs` threads, `0` uses one thread per CPU.
+    pub fn render(
+        mold: &Mold,
+        templates: Vec<(PathBuf, Option<String>)>,
+        jobs: usize,
+        show_missing: bool,
+    ) -> anyhow::Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+        let rendered = pool.install(|| {
+            templates
+                .into_par_iter()
+                .filter_map(|(template, namespace)| {
+                    let start = Instant::now();
+                    let rendered = mold
+                        .render_file_with_missing(&template, namespace.as_deref(), show_missing)
+                        .ok()?;
+                    info!(
+                        "rendered `{}` in {:.2?}",
+                        template.display(),
+                        start.elapsed()
+                    );
+                    Some(((template, namespace), rendered))
+                })
+                .collect()
+        });
+        Ok(Prerendered(rendered))
+    }
+
+    /// Returns the render of `template` with `namespace` if it was rendered ahead of time.
+    pub fn get(&self, template: &Path, namespace: Option<&str>) -> Option<Rendered> {
+        self.0
+            .get(&(template.to_path_buf(), namespace.map(str::to_string)))
+            .cloned()
+    }
+}
@@ -0,0 +1,200 @@
+use colored::{Color, Colorize};
+use similar::{ChangeTag, DiffOp, TextDiff};
+use std::io;
+
+/// Narrowest width at which a side-by-side diff is displayed, the inline format is used below it.
+pub const MIN_WIDTH: usize = 60;
+
+/// Separator between the old and the new column.
+const SEPARATOR: &str = " | ";
+/// Width of the line number and the sign in front of every line.
+const GUTTER_WIDTH: usize = 7;
+/// Tabs are replaced with spaces so that every character takes up exactly one column.
+const TAB: &str = "    ";
+
+/// A line of the old or the new file split into segments, the changed ones are emphasized.
+#[derive(Clone)]
+struct Cell {
+    index: Option<usize>,
+    tag: ChangeTag,
+    segments: Vec<(bool, String)>,
+}
+
+impl Cell {
+    fn color(&self) -> Option<Color> {
+        match self.tag {
+            ChangeTag::Delete => Some(Color::Red),
+            ChangeTag::Insert => Some(Color::Green),
+            ChangeTag::Equal => None,
+        }
+    }
+
+    fn sign(&self) -> &'static str {
+        match self.tag {
+            ChangeTag::Delete => "-",
+            ChangeTag::Insert => "+",
+            ChangeTag::Equal => " ",
+        }
+    }
+
+    /// Splits the segments into lines of at most `width` characters, long lines are wrapped
+    /// rather than truncated so that no change is hidden.
+    fn wrap(&self, width: usize) -> Vec<Vec<(bool, String)>> {
+        let mut lines = vec![vec![]];
+        let mut len = 0;
+        for (emphasized, segment) in &self.segments {
+            let mut current = String::new();
+            for c in segment.chars() {
+                if len == width {
+                    if !current.is_empty() {
+                        lines
+                            .last_mut()
+                            .unwrap()
+                            .push((*emphasized, std::mem::take(&mut current)));
+                    }
+                    lines.push(vec![]);
+                    len = 0;
+                }
+                current.push(c);
+                len += 1;
+            }
+            if !current.is_empty() {
+                lines.last_mut().unwrap().push((*emphasized, current));
+            }
+        }
+        lines
+    }
+}
+
+/// Writes one side of a row. The line number and sign are only written on the first line of a
+/// wrapped cell, with `pad` the text is padded to `width` so that the next column is aligned.
+fn write_cell<W: io::Write>(
+    writer: &mut W,
+    cell: Option<&Cell>,
+    line: Option<&Vec<(bool, String)>>,
+    first: bool,
+    width: usize,
+    pad: bool,
+) -> io::Result<()> {
+    match cell.filter(|_| first) {
+        Some(cell) => {
+            let number = cell
+                .index
+                .map(|index| (index + 1).to_string())
+                .unwrap_or_default();
+            let sign = match cell.color() {
+                Some(color) => cell.sign().color(color),
+                None => cell.sign().normal(),
+            };
+            write!(writer, "{:>5} {}", number, sign)?
+        }
+        None => write!(writer, "{:1$}", "", GUTTER_WIDTH)?,
+    }
+    let mut len = 0;
+    for (emphasized, segment) in line.into_iter().flatten() {
+        len += segment.chars().count();
+        match cell.and_then(Cell::color) {
+            Some(color) if *emphasized => {
+                write!(writer, "{}", segment.color(color).underline().on_black())?
+            }
+            Some(color) => write!(writer, "{}", segment.color(color))?,
+            None => writer.write_all(segment.as_bytes())?,
+        }
+    }
+    if pad {
+        write!(writer, "{:1$}", "", width.saturating_sub(len))?;
+    }
+    Ok(())
+}
+
+/// Writes the `groups` of `diff` in two columns fitting into `width`, the old content on the
+/// left and the new content on the right. Deleted and inserted lines of the same change are
+/// placed next to each other.
+pub fn write<'a, W: io::Write>(
+    writer: &mut W,
+    diff: &'a TextDiff<'a, 'a, 'a, str>,
+    groups: &[Vec<DiffOp>],
+    width: usize,
+) -> io::Result<()> {
+    let text_width = (width - SEPARATOR.len()) / 2 - GUTTER_WIDTH;
+    for (idx, group) in groups.iter().enumerate() {
+        if idx > 0 {
+            writeln!(writer, "{:-^1$}", "-", width)?;
+        }
+        for op in group {
+            let mut old = vec![];
+            let mut new = vec![];
+            for change in diff.iter_inline_changes(op) {
+                let segments = change
+                    .iter_strings_lossy()
+                    .map(|(emphasized, value)| {
+                        let value = value.trim_end_matches(['\n', '\r']).replace('\t', TAB);
+                        (emphasized, value)
+                    })
+                    .collect();
+                let cell = |index| Cell {
+                    index,
+                    tag: change.tag(),
+                    segments,
+                };
+                match change.tag() {
+                    ChangeTag::Delete => old.push(cell(change.old_index())),
+                    ChangeTag::Insert => new.push(cell(change.new_index())),
+                    ChangeTag::Equal => {
+                        let old_cell = cell(change.old_index());
+                        new.push(Cell {
+                            index: change.new_index(),
+                            ..old_cell.clone()
+                        });
+                        old.push(old_cell);
+                    }
+                }
+            }
+            for row in 0..old.len().max(new.len()) {
+                let (old, new) = (old.get(row), new.get(row));
+                let old_lines = old.map(|cell| cell.wrap(text_width)).unwrap_or_default();
+                let new_lines = new.map(|cell| cell.wrap(text_width)).unwrap_or_default();
+                for line in 0..old_lines.len().max(new_lines.len()) {
+                    write_cell(
+                        writer,
+                        old,
+                        old_lines.get(line),
+                        line == 0,
+                        text_width,
+                        true,
+                    )?;
+                    writer.write_all(SEPARATOR.as_bytes())?;
+                    write_cell(
+                        writer,
+                        new,
+                        new_lines.get(line),
+                        line == 0,
+                        text_width,
+                        false,
+                    )?;
+                    writeln!(writer)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the number of columns of the terminal connected to standard output.
+#[cfg(unix)]
+pub fn terminal_width() -> Option<usize> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` to the passed pointer
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(not(unix))]
+pub fn terminal_width() -> Option<usize> {
+    None
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Provides the content of templates and of the files included by them, so that templates don't
+/// have to live on the filesystem.
+pub trait TemplateSource: Debug + Send + Sync {
+    /// Returns the content of the file at `path`.
+    fn read(&self, path: &Path) -> io::Result<String>;
+
+    /// Whether there is a file at `path`.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Returns the path that identifies the file at `path`, used to detect include cycles when
+    /// the same file is reachable through different paths.
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        path.to_path_buf()
+    }
+}
+
+/// Reads templates from the filesystem, this is the default source.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileSystemSource;
+
+impl TemplateSource for FileSystemSource {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+/// Templates held in memory keyed by their path.
+#[derive(Debug, Default, Clone)]
+pub struct MemorySource {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file with `content` at `path`, replacing a previous file at the same path.
+    pub fn insert(&mut self, path: impl Into<PathBuf>, content: impl Into<String>) {
+        self.files.insert(normalize(&path.into()), content.into());
+    }
+}
+
+impl TemplateSource for MemorySource {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.files.get(&normalize(path)).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no template at `{}`", path.display()),
+            )
+        })
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        normalize(path)
+    }
+}
+
+/// Removes `.` components and resolves `..` components lexically, so that `./a/../b` and `b`
+/// refer to the same file.
+fn normalize(path: &Path) -> PathBuf {
+    use std::path::Component;
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
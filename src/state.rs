@@ -0,0 +1,162 @@
+use anyhow::{Context as ErrorContext, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const STATE_ENV: &str = "MOLD_STATE_FILE";
+const STATE_FILE_NAME: &str = "mold/state.yaml";
+
+/// Returns the location of the state file. `$MOLD_STATE_FILE` takes precedence over the default
+/// `$XDG_STATE_HOME/mold/state.yaml`.
+pub fn default_state_file() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(STATE_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    dirs::state_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("state")))
+        .map(|dir| dir.join(STATE_FILE_NAME))
+}
+
+/// Returns a hex encoded SHA-256 hash of `content`.
+pub fn hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Records which context and namespace were used by the last `apply`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AppliedState {
+    pub context_file: PathBuf,
+    /// Context files merged on top of `context_file`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overlays: Vec<PathBuf>,
+    pub namespace: Option<String>,
+    pub timestamp: String,
+}
+
+impl AppliedState {
+    /// Creates the state of an apply of the merged `context_files`, there must be at least one.
+    pub fn new(context_files: &[PathBuf], namespace: Option<&str>) -> Self {
+        Self {
+            context_file: canonical(&context_files[0]),
+            overlays: context_files[1..]
+                .iter()
+                .map(|file| canonical(file))
+                .collect(),
+            namespace: namespace.map(str::to_string),
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        }
+    }
+
+    /// Returns the context file followed by its overlays.
+    pub fn context_files(&self) -> Vec<PathBuf> {
+        std::iter::once(&self.context_file)
+            .chain(&self.overlays)
+            .cloned()
+            .collect()
+    }
+
+    /// Checks whether this state was recorded for the given context file.
+    pub fn is_for(&self, context_file: &Path) -> bool {
+        canonical(context_file) == self.context_file
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct State {
+    #[serde(default)]
+    pub applied: Option<AppliedState>,
+    /// Hashes of the content last written by mold keyed by the output path.
+    #[serde(default)]
+    outputs: BTreeMap<PathBuf, String>,
+}
+
+impl State {
+    /// Loads the state from `path`, a missing state file results in an empty state.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(data) => serde_yaml::from_slice(&data).context("state deserialization error"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).context("failed to read state file"),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("failed to create state directory")?;
+        }
+        let data = serde_yaml::to_string(self).context("state serialization error")?;
+        std::fs::write(path, data).context("failed to write state file")
+    }
+
+    /// Returns the hash of the content that was last written to `output`.
+    pub fn output_hash(&self, output: &Path) -> Option<&str> {
+        self.outputs.get(&canonical(output)).map(String::as_str)
+    }
+}
+
+/// State loaded from the state file. As the state is not essential for rendering all failures
+/// are only reported as warnings.
+pub struct StateFile {
+    path: Option<PathBuf>,
+    state: State,
+    modified: bool,
+}
+
+impl StateFile {
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let state = path
+            .as_deref()
+            .and_then(|path| match State::load(path) {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    warn!("failed to load state from `{}` - {:?}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            state,
+            modified: false,
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn record_applied(&mut self, context_files: &[PathBuf], namespace: Option<&str>) {
+        self.state.applied = Some(AppliedState::new(context_files, namespace));
+        self.modified = true;
+    }
+
+    /// Records the hash of `content` written to `output`. Must be called after the file is
+    /// written so that the path can be canonicalized.
+    pub fn record_output(&mut self, output: &Path, content: &[u8]) {
+        self.state.outputs.insert(canonical(output), hash(content));
+        self.modified = true;
+    }
+
+    /// Saves the state if it was modified.
+    pub fn save(&mut self) {
+        if !self.modified {
+            return;
+        }
+        match &self.path {
+            Some(path) => {
+                if let Err(e) = self.state.save(path) {
+                    warn!("failed to save state to `{}` - {:?}", path.display(), e);
+                }
+            }
+            None => warn!("no location for the state file, state not saved"),
+        }
+        self.modified = false;
+    }
+}
@@ -0,0 +1,157 @@
+use indexmap::IndexMap;
+
+/// A line of an X resources file that couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnparsedLine {
+    /// 1-based line number.
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+/// Resources parsed from an X resources file.
+#[derive(Debug, Default, Clone)]
+pub struct Xresources {
+    /// Resource values keyed by the resource name without the class or instance prefix, like
+    /// `color0` for `*.color0`.
+    pub resources: IndexMap<String, String>,
+    pub unparsed: Vec<UnparsedLine>,
+}
+
+/// Parses resources in the `*name: value` and `prefix.name: value` forms. Comments starting with
+/// `!` and empty lines are skipped, all other lines that are not resources are reported as
+/// unparsed. Later definitions of the same name override earlier ones.
+pub fn parse(content: &str) -> Xresources {
+    let mut xresources = Xresources::default();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('!') {
+            continue;
+        }
+        let mut unparsed = |reason| {
+            xresources.unparsed.push(UnparsedLine {
+                line: i + 1,
+                reason,
+            })
+        };
+        if line.starts_with('#') {
+            unparsed("preprocessor directives are not supported");
+            continue;
+        }
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => {
+                unparsed("expected `name: value`");
+                continue;
+            }
+        };
+        let name = name.rsplit(['*', '.']).next().unwrap_or_default();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            unparsed("invalid resource name");
+            continue;
+        }
+        xresources
+            .resources
+            .insert(name.to_string(), value.to_string());
+    }
+    xresources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_forms() {
+        let xresources = parse(
+            "*background: #282828\n\
+             URxvt.foreground:#ebdbb2\n\
+             *.color0 :  #1d2021  \n\
+             XTerm*vt100.color1: #cc241d\n\
+             font: JetBrains Mono:size=11\n",
+        );
+        let resources: Vec<_> = xresources
+            .resources
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            resources,
+            [
+                ("background", "#282828"),
+                ("foreground", "#ebdbb2"),
+                ("color0", "#1d2021"),
+                ("color1", "#cc241d"),
+                ("font", "JetBrains Mono:size=11"),
+            ]
+        );
+        assert!(xresources.unparsed.is_empty());
+    }
+
+    #[test]
+    fn later_definitions_override_earlier_ones() {
+        let xresources = parse("*color0: #000000\n*color1: #ff0000\nURxvt.color0: #111111\n");
+        assert_eq!(xresources.resources["color0"], "#111111");
+        // the position of the first definition is kept
+        assert_eq!(
+            xresources.resources.keys().collect::<Vec<_>>(),
+            ["color0", "color1"]
+        );
+    }
+
+    #[test]
+    fn comments_and_empty_lines_are_skipped() {
+        let xresources =
+            parse("! gruvbox\n\n  ! indented: comment\n*color0: #282828 ! not a comment\n");
+        assert_eq!(xresources.resources.len(), 1);
+        assert_eq!(xresources.resources["color0"], "#282828 ! not a comment");
+        assert!(xresources.unparsed.is_empty());
+    }
+
+    #[test]
+    fn unparsed_lines_are_reported_with_their_numbers() {
+        let xresources = parse(
+            "! colors\n\
+             #define BG #282828\n\
+             *background: BG\n\
+             \n\
+             #include \"colors.Xresources\"\n\
+             just text\n\
+             *: #000000\n\
+             *my color: #ffffff\n",
+        );
+        assert_eq!(xresources.resources["background"], "BG");
+        assert_eq!(
+            xresources.unparsed,
+            [
+                UnparsedLine {
+                    line: 2,
+                    reason: "preprocessor directives are not supported"
+                },
+                UnparsedLine {
+                    line: 5,
+                    reason: "preprocessor directives are not supported"
+                },
+                UnparsedLine {
+                    line: 6,
+                    reason: "expected `name: value`"
+                },
+                UnparsedLine {
+                    line: 7,
+                    reason: "invalid resource name"
+                },
+                UnparsedLine {
+                    line: 8,
+                    reason: "invalid resource name"
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let xresources = parse("*color0: #282828\r\n#define X\r\n*color1: #cc241d\r\n");
+        assert_eq!(xresources.resources["color0"], "#282828");
+        assert_eq!(xresources.resources["color1"], "#cc241d");
+        assert_eq!(xresources.unparsed[0].line, 2);
+    }
+}